@@ -1,7 +1,7 @@
 // spawns the hyper server on a separate thread
 use hyper::body::Incoming;
 use hyper::service::service_fn;
-use hyper::Request;
+use hyper::{Method, Request};
 use hyper_util::rt::TokioIo;
 use hyper_util::server::conn::auto;
 use std::net::SocketAddr;
@@ -10,6 +10,7 @@ use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 
 use super::fips;
+use super::fips::proxy::CertificateAuthority;
 use super::PaintLogsCallbacks;
 use crate::configuration::configuration::Config;
 use tokio::sync::Mutex as AsyncMutex;
@@ -25,6 +26,7 @@ pub fn spawn_backend(
     configuration: &Arc<AsyncMutex<Config>>,
     addr: &SocketAddr,
     logger: &Arc<PaintLogsCallbacks>,
+    authority: Option<Arc<CertificateAuthority>>,
 ) -> JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
     let capture_configuration = configuration.clone();
     let capture_logger = logger.clone();
@@ -34,23 +36,32 @@ pub fn spawn_backend(
         let listener = TcpListener::bind(addr).await?;
         
         loop {
-            let (stream, _) = listener.accept().await?;
+            let (stream, client) = listener.accept().await?;
             let io = TokioIo::new(stream);
             
             let config = capture_configuration.clone();
             let logger = capture_logger.clone();
+            let authority = authority.clone();
             
             tokio::task::spawn(async move {
-                let service = service_fn(move |req: Request<Incoming>| {
+                let service = service_fn(move |mut req: Request<Incoming>| {
+                    // rules can tell clients apart, e.g. to rate limit them
+                    req.extensions_mut().insert(client);
                     let config = config.clone();
                     let logger = logger.clone();
+                    let authority = authority.clone();
                     async move {
-                        fips::routes(req, config, &logger).await
+                        if req.method() == Method::CONNECT {
+                            fips::proxy::connect(req, authority, config, logger)
+                                .await
+                        } else {
+                            fips::routes(req, config, &logger).await
+                        }
                     }
                 });
                 
                 if let Err(err) = auto::Builder::new(hyper_util::rt::TokioExecutor::new())
-                    .serve_connection(io, service)
+                    .serve_connection_with_upgrades(io, service)
                     .await
                 {
                     eprintln!("Error serving connection: {:?}", err);
@@ -62,14 +73,34 @@ pub fn spawn_backend(
 
 #[cfg(not(feature = "ui"))]
 fn define_log_callbacks() -> PaintLogsCallbacks {
-    use crate::utility::log::Loggable;
+    use crate::utility::log::{Loggable, LoggableType};
 
-    let log = Box::new(|message: &Loggable| info!("{:?}", message.message));
+    let log = Box::new(|message: &Loggable| match &message.message_type {
+        LoggableType::IncomingRequestAtFfips(i) => {
+            info!("Incoming Request {i}")
+        }
+        LoggableType::OutgoingRequestToServer(i) => {
+            info!("Outgoing Request {i}")
+        }
+        LoggableType::OutGoingResponseFromFips(i) => {
+            info!("Outgoing Response {i}")
+        }
+        LoggableType::IncomingWebSocketMessage(i) => {
+            info!("Incoming WebSocket Message {i}")
+        }
+        LoggableType::OutgoingWebSocketMessage(i) => {
+            info!("Outgoing WebSocket Message {i}")
+        }
+        LoggableType::Plain => info!("{}", message.message),
+    });
     PaintLogsCallbacks(log)
 }
 
 #[cfg(not(feature = "ui"))]
-pub fn setup() -> (Option<PhantomData<dyn Any>>, Option<PhantomData<dyn Any>>, Arc<PaintLogsCallbacks>) {
+type NoFrontend = Option<PhantomData<dyn Any>>;
+
+#[cfg(not(feature = "ui"))]
+pub fn setup() -> (NoFrontend, NoFrontend, Arc<PaintLogsCallbacks>) {
     let logging = Arc::new(define_log_callbacks());
     (
         None,
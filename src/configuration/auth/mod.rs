@@ -0,0 +1,169 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::{header::AUTHORIZATION, HeaderMap};
+
+use super::configuration::Auth;
+use super::intermediary::Intermediary;
+use super::jwt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Authenticated,
+    Missing,
+    Invalid,
+}
+
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let decoded = STANDARD.decode(value.strip_prefix("Basic ")?).ok()?;
+    let (user, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+// valid credentials of any configured kind authenticate, credentials of a
+// kind that is not configured count as missing
+pub fn check(auth: &Auth, intermediary: &Intermediary) -> Outcome {
+    let mut checked = Vec::new();
+
+    if let Some(users) = &auth.basic {
+        if let Some((user, password)) =
+            basic_credentials(&intermediary.headers)
+        {
+            checked.push(users.iter().any(|basic| {
+                basic.user == user && basic.password == password
+            }));
+        }
+    }
+    if let Some(tokens) = &auth.bearer {
+        if let Some(token) = jwt::bearer(&intermediary.headers) {
+            checked.push(tokens.iter().any(|t| t == token));
+        }
+    }
+    if let Some(api_key) = &auth.api_key {
+        let from_header = api_key.header.as_ref().and_then(|name| {
+            intermediary.headers.get(name)?.to_str().ok().map(str::to_string)
+        });
+        let from_query = api_key.query.as_ref().and_then(|name| {
+            let query = intermediary.uri.as_ref()?.query()?;
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        });
+        for key in from_header.into_iter().chain(from_query) {
+            checked.push(api_key.keys.contains(&key));
+        }
+    }
+
+    if checked.iter().any(|valid| *valid) {
+        Outcome::Authenticated
+    } else if checked.is_empty() {
+        Outcome::Missing
+    } else {
+        Outcome::Invalid
+    }
+}
+
+// one challenge per scheme, a refused bearer token is named in its challenge
+pub fn challenges(auth: &Auth, intermediary: &Intermediary) -> Vec<String> {
+    let realm = auth.realm.as_deref().unwrap_or("fips");
+    let mut challenges = Vec::new();
+    if auth.basic.is_some() {
+        challenges.push(format!("Basic realm=\"{realm}\""));
+    }
+    if auth.bearer.is_some() {
+        challenges.push(match jwt::bearer(&intermediary.headers) {
+            Some(_) => {
+                format!("Bearer realm=\"{realm}\", error=\"invalid_token\"")
+            }
+            None => format!("Bearer realm=\"{realm}\""),
+        });
+    }
+    challenges
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+
+    const AUTH: &str = r#"
+basic:
+  - user: ann
+    password: secret
+bearer: [t0ken]
+apiKey:
+  header: x-api-key
+  query: key
+  keys: [k3y]
+realm: api
+"#;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Intermediary {
+        let mut request = Intermediary::request(Method::GET, uri, b"");
+        for (name, value) in headers {
+            let name = http::HeaderName::from_bytes(name.as_bytes()).unwrap();
+            request.headers.insert(name, value.parse().unwrap());
+        }
+        request
+    }
+
+    fn outcome(uri: &str, headers: &[(&str, &str)]) -> Outcome {
+        let auth: Auth = serde_yaml::from_str(AUTH).unwrap();
+        check(&auth, &request(uri, headers))
+    }
+
+    #[test]
+    fn any_valid_credentials_authenticate() {
+        // ann:secret
+        let basic = [("authorization", "Basic YW5uOnNlY3JldA==")];
+        assert_eq!(outcome("/", &basic), Outcome::Authenticated);
+        let bearer = [("authorization", "Bearer t0ken")];
+        assert_eq!(outcome("/", &bearer), Outcome::Authenticated);
+        let header = [("x-api-key", "k3y")];
+        assert_eq!(outcome("/", &header), Outcome::Authenticated);
+        assert_eq!(outcome("/?key=k3y", &[]), Outcome::Authenticated);
+        // a valid key makes up for a wrong one
+        assert_eq!(
+            outcome("/?key=k3y", &[("x-api-key", "no")]),
+            Outcome::Authenticated
+        );
+    }
+
+    #[test]
+    fn wrong_credentials_are_invalid() {
+        // ann:wrong
+        let basic = [("authorization", "Basic YW5uOndyb25n")];
+        assert_eq!(outcome("/", &basic), Outcome::Invalid);
+        let bearer = [("authorization", "Bearer other")];
+        assert_eq!(outcome("/", &bearer), Outcome::Invalid);
+        assert_eq!(outcome("/?key=no", &[]), Outcome::Invalid);
+    }
+
+    #[test]
+    fn unconfigured_kinds_count_as_missing() {
+        assert_eq!(outcome("/", &[]), Outcome::Missing);
+        let auth: Auth = serde_yaml::from_str("bearer: [t0ken]").unwrap();
+        let basic =
+            request("/", &[("authorization", "Basic YW5uOnNlY3JldA==")]);
+        assert_eq!(check(&auth, &basic), Outcome::Missing);
+    }
+
+    #[test]
+    fn challenges_name_the_realm_and_refused_tokens() {
+        let auth: Auth = serde_yaml::from_str(AUTH).unwrap();
+        assert_eq!(
+            challenges(&auth, &request("/", &[])),
+            ["Basic realm=\"api\"", "Bearer realm=\"api\""]
+        );
+        let refused = request("/", &[("authorization", "Bearer other")]);
+        assert_eq!(
+            challenges(&auth, &refused)[1],
+            "Bearer realm=\"api\", error=\"invalid_token\""
+        );
+        let auth: Auth = serde_yaml::from_str("bearer: [t0ken]").unwrap();
+        assert_eq!(
+            challenges(&auth, &request("/", &[])),
+            ["Bearer realm=\"fips\""]
+        );
+    }
+}
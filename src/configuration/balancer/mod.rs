@@ -0,0 +1,235 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Request;
+use rand::Rng;
+
+use super::configuration::{
+    BalancingStrategy, ForwardUri, HealthCheck, LoadBalancing, Upstream,
+};
+use super::seed::SharedRng;
+use crate::fips::proxy::CLIENT;
+
+const DEFAULT_HEALTH_CHECK_INTERVAL_MS: u64 = 5000;
+const HEALTH_CHECK_TIMEOUT_MS: u64 = 2000;
+
+// picks one of several upstreams for a forwarding rule
+#[derive(Debug)]
+pub struct Balancer {
+    upstreams: Vec<Upstream>,
+    strategy: BalancingStrategy,
+    healthy: Vec<AtomicBool>,
+    next: AtomicUsize,
+    rng: SharedRng,
+}
+
+impl Balancer {
+    pub fn new(
+        forward_uri: &ForwardUri,
+        load_balancing: Option<&LoadBalancing>,
+        rng: SharedRng,
+    ) -> Option<Arc<Balancer>> {
+        let upstreams = match forward_uri {
+            ForwardUri::Single(_) => return None,
+            ForwardUri::Balanced(upstreams) => upstreams.clone(),
+        };
+
+        let balancer = Arc::new(Balancer {
+            healthy: upstreams.iter().map(|_| AtomicBool::new(true)).collect(),
+            upstreams,
+            strategy: load_balancing
+                .and_then(|lb| lb.strategy.clone())
+                .unwrap_or_default(),
+            next: AtomicUsize::new(0),
+            rng,
+        });
+
+        if let Some(health_check) =
+            load_balancing.and_then(|lb| lb.health_check.as_ref())
+        {
+            // checks need a runtime, e.g. when loading the schema there is none
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(run_health_checks(
+                    Arc::downgrade(&balancer),
+                    health_check.clone(),
+                ));
+            }
+        }
+        Some(balancer)
+    }
+
+    pub fn select(&self) -> Option<&Upstream> {
+        let mut candidates: Vec<usize> = (0..self.upstreams.len())
+            .filter(|&idx| self.healthy[idx].load(Ordering::Relaxed))
+            .collect();
+        // if nothing is healthy, keep sending traffic so fallbacks can apply
+        if candidates.is_empty() {
+            candidates = (0..self.upstreams.len()).collect();
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let idx = match self.strategy {
+            BalancingStrategy::RoundRobin => {
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates[next % candidates.len()]
+            }
+            BalancingStrategy::Random => {
+                let mut rng = self.rng.lock().unwrap();
+                candidates[rng.gen_range(0, candidates.len())]
+            }
+            BalancingStrategy::Weighted => {
+                let weight =
+                    |idx: &usize| self.upstreams[*idx].weight.unwrap_or(1);
+                let total: u32 = candidates.iter().map(weight).sum();
+                if total == 0 {
+                    candidates[0]
+                } else {
+                    let mut pick =
+                        self.rng.lock().unwrap().gen_range(0, total);
+                    *candidates
+                        .iter()
+                        .find(|idx| {
+                            if pick < weight(idx) {
+                                true
+                            } else {
+                                pick -= weight(idx);
+                                false
+                            }
+                        })
+                        .unwrap_or(&candidates[0])
+                }
+            }
+            BalancingStrategy::FirstHealthy => candidates[0],
+        };
+        self.upstreams.get(idx)
+    }
+}
+
+async fn run_health_checks(
+    balancer: Weak<Balancer>,
+    health_check: HealthCheck,
+) {
+    let interval = Duration::from_millis(
+        health_check
+            .interval
+            .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_MS),
+    );
+
+    // the task ends once the rule is dropped, e.g. after a config reload
+    while let Some(balancer) = balancer.upgrade() {
+        for (idx, upstream) in balancer.upstreams.iter().enumerate() {
+            let uri = format!(
+                "{}{}",
+                upstream.uri.trim_end_matches('/'),
+                health_check.path
+            );
+            let is_healthy =
+                match Request::get(uri).body(Full::new(Bytes::new())) {
+                    Ok(request) => matches!(
+                        tokio::time::timeout(
+                            Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS),
+                            CLIENT.request(request),
+                        )
+                        .await,
+                        Ok(Ok(resp)) if resp.status().is_success()
+                    ),
+                    Err(_) => false,
+                };
+            balancer.healthy[idx].store(is_healthy, Ordering::Relaxed);
+        }
+        drop(balancer);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::seed;
+    use super::*;
+
+    fn balancer(strategy: BalancingStrategy, weights: &[u32]) -> Arc<Balancer> {
+        let upstreams = weights
+            .iter()
+            .enumerate()
+            .map(|(idx, weight)| Upstream {
+                uri: format!("http://upstream-{idx}"),
+                weight: Some(*weight),
+            })
+            .collect();
+        let load_balancing = LoadBalancing {
+            strategy: Some(strategy),
+            health_check: None,
+        };
+        Balancer::new(
+            &ForwardUri::Balanced(upstreams),
+            Some(&load_balancing),
+            seed::rng(Some(7), 0),
+        )
+        .unwrap()
+    }
+
+    fn pick(balancer: &Balancer) -> &str {
+        &balancer.select().unwrap().uri
+    }
+
+    #[test]
+    fn single_uri_needs_no_balancer() {
+        let forward_uri = ForwardUri::Single("http://upstream".into());
+        let rng = seed::rng(None, 0);
+        assert!(Balancer::new(&forward_uri, None, rng).is_none());
+    }
+
+    #[test]
+    fn round_robin_cycles_through_upstreams() {
+        let balancer = balancer(BalancingStrategy::RoundRobin, &[1, 1, 1]);
+        let picks: Vec<&str> = (0..4).map(|_| pick(&balancer)).collect();
+        assert_eq!(
+            picks,
+            [
+                "http://upstream-0",
+                "http://upstream-1",
+                "http://upstream-2",
+                "http://upstream-0"
+            ]
+        );
+    }
+
+    #[test]
+    fn unhealthy_upstreams_are_skipped() {
+        let balancer = balancer(BalancingStrategy::FirstHealthy, &[1, 1]);
+        assert_eq!(pick(&balancer), "http://upstream-0");
+        balancer.healthy[0].store(false, Ordering::Relaxed);
+        assert_eq!(pick(&balancer), "http://upstream-1");
+    }
+
+    #[test]
+    fn all_unhealthy_still_selects() {
+        let balancer = balancer(BalancingStrategy::FirstHealthy, &[1, 1]);
+        for healthy in &balancer.healthy {
+            healthy.store(false, Ordering::Relaxed);
+        }
+        assert_eq!(pick(&balancer), "http://upstream-0");
+    }
+
+    #[test]
+    fn weighted_never_picks_zero_weight() {
+        let balancer = balancer(BalancingStrategy::Weighted, &[0, 3]);
+        for _ in 0..50 {
+            assert_eq!(pick(&balancer), "http://upstream-1");
+        }
+    }
+
+    #[test]
+    fn random_is_reproducible_with_a_seed() {
+        let first = balancer(BalancingStrategy::Random, &[1, 1, 1]);
+        let second = balancer(BalancingStrategy::Random, &[1, 1, 1]);
+        for _ in 0..20 {
+            assert_eq!(pick(&first), pick(&second));
+        }
+    }
+}
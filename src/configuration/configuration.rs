@@ -1,5 +1,5 @@
-use eyre::{eyre, Result};
 use lazy_static::lazy_static;
+use regex::Regex;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -10,7 +10,9 @@ use crate::plugin_registry::ExternalFunctions;
 
 use super::loader::{DeserializationError, YamlFileLoader};
 
-use super::rule::{then::Then, when::When, Rule};
+use super::rule::{
+    error::ConfigurationError, state::RuleState, then::Then, when::When, Rule,
+};
 use super::ruleset::RuleSet;
 
 lazy_static! {
@@ -55,36 +57,377 @@ pub struct Match {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
-enum RuleType {
-    NonForwarding,
-    Forwarding,
+pub struct XPathMatch {
+    pub xpath: String,
+    /// Regex the string value of the xpath result has to match, without it
+    /// the result only has to be non-empty or true
+    pub value: Option<String>,
+    /// Prefixes usable in the xpath, `soap` and `soap12` are predefined
+    pub namespaces: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GraphQLMatch {
+    /// Regex for the operation name
+    #[serde(rename = "operationName")]
+    pub operation_name: Option<String>,
+    /// query, mutation or subscription
+    #[serde(rename = "operationType")]
+    pub operation_type: Option<String>,
+    /// Dot paths into the variables and the regex their value has to match
+    pub variables: Option<HashMap<String, String>>,
+}
+
+/// The bearer token sent in the Authorization header, its signature is only
+/// verified when a key is configured
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JwtMatch {
+    /// PEM file with an RSA, EC or Ed25519 public key, relative to the rule
+    /// file
+    #[serde(rename = "keyFile")]
+    pub key_file: Option<String>,
+    /// JWKS file, keys are picked by the token's `kid`
+    #[serde(rename = "jwksFile")]
+    pub jwks_file: Option<String>,
+    /// Shared secret of HMAC signed tokens
+    pub secret: Option<String>,
+    /// Also match expired tokens
+    #[serde(rename = "allowExpired")]
+    pub allow_expired: Option<bool>,
+    /// Dot paths into the claims and the regex their value has to match,
+    /// one item has to match for arrays
+    pub claims: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FileMatch {
+    /// Name of the form field the file was uploaded with
+    pub field: Option<String>,
+    #[serde(rename = "fileName")]
+    pub file_name: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    /// Size limits in bytes
+    #[serde(rename = "minSize")]
+    pub min_size: Option<u64>,
+    #[serde(rename = "maxSize")]
+    pub max_size: Option<u64>,
+}
+
+/// A host name in which `*` stands for any characters, or a regex
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HostMatch {
+    Name(String),
+    Regex { regex: String },
+}
+
+impl HostMatch {
+    // names match case insensitively, like hosts are compared
+    pub fn regex(&self) -> Result<Regex, ConfigurationError> {
+        match self {
+            HostMatch::Name(name) => {
+                let pattern = name
+                    .split('*')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".*");
+                Ok(Regex::new(&format!("(?i)^{pattern}$"))?)
+            }
+            HostMatch::Regex { regex } => Ok(Regex::new(regex)?),
+        }
+    }
+}
+
+/// One or several values of a header
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HeaderValues {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl HeaderValues {
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        match self {
+            HeaderValues::Single(value) => std::slice::from_ref(value).iter(),
+            HeaderValues::Multiple(values) => values.iter(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    /// Seconds the cookie is kept, a session cookie without it
+    #[serde(rename = "maxAge")]
+    pub max_age: Option<i64>,
+    #[serde(rename = "sameSite")]
+    pub same_site: Option<SameSite>,
+    pub secure: Option<bool>,
+    #[serde(rename = "httpOnly")]
+    pub http_only: Option<bool>,
+}
+
+/// A cookie the client is told to remove, given by name or with the path and
+/// domain it was set for
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum DeleteCookie {
+    Name(String),
+    Cookie {
+        name: String,
+        /// `/` by default
+        path: Option<String>,
+        domain: Option<String>,
+    },
+}
+
+/// Credentials a rule requires, any of the configured ones is accepted
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Auth {
+    pub basic: Option<Vec<BasicUser>>,
+    /// Static tokens sent as `Authorization: Bearer <token>`
+    pub bearer: Option<Vec<String>>,
+    #[serde(rename = "apiKey")]
+    pub api_key: Option<ApiKey>,
+    /// Realm of the WWW-Authenticate challenge, `fips` by default
+    pub realm: Option<String>,
+    /// Answer to requests without credentials, 401 by default
+    pub missing: Option<AuthResponse>,
+    /// Answer to requests with wrong credentials, 401 by default
+    pub invalid: Option<AuthResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BasicUser {
+    pub user: String,
+    pub password: String,
+}
+
+/// A key sent in a header or query parameter
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiKey {
+    pub header: Option<String>,
+    pub query: Option<String>,
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuthResponse {
+    pub status: Option<String>,
+    pub body: Option<Value>,
+    pub headers: Option<HashMap<String, HeaderValues>>,
+}
+
+/// A client allowed to request tokens from an OAuth rule
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OAuthClient {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    /// Checked for the client credentials grant and when sent, public
+    /// clients without a secret are allowed
+    #[serde(rename = "clientSecret")]
+    pub client_secret: Option<String>,
+    /// Any redirect uri is accepted when not given
+    #[serde(rename = "redirectUris")]
+    pub redirect_uris: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifyRequest {
     #[serde(rename = "setHeaders")]
-    add_headers: Option<HashMap<String, String>>,
+    pub add_headers: Option<HashMap<String, HeaderValues>>,
+    /// Only forward these headers of the incoming request
     #[serde(rename = "keepHeaders")]
-    keep_headers: Option<Vec<String>>,
+    pub keep_headers: Option<Vec<String>>,
+    #[serde(rename = "deleteHeaders")]
+    pub delete_headers: Option<Vec<String>>,
+    pub method: Option<String>,
+    #[serde(rename = "setQuery")]
+    pub set_query: Option<HashMap<String, String>>,
+    pub body: Option<Vec<BodyManipulation>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifyResponseFips {
+    /// Replace headers of the same name
     #[serde(rename = "setHeaders")]
-    pub set_headers: Option<HashMap<String, String>>,
+    pub set_headers: Option<HashMap<String, HeaderValues>>,
+    /// Add to headers of the same name
+    #[serde(rename = "appendHeaders")]
+    pub append_headers: Option<HashMap<String, HeaderValues>>,
     #[serde(rename = "deleteHeaders")]
     pub delete_headers: Option<Vec<String>>,
+    #[serde(rename = "setCookies")]
+    pub set_cookies: Option<Vec<SetCookie>>,
+    /// Cookies the client is told to remove
+    #[serde(rename = "deleteCookies")]
+    pub delete_cookies: Option<Vec<DeleteCookie>>,
     pub body: Option<Vec<BodyManipulation>>,
+    /// Text level changes, applied before all json modifications
+    pub text: Option<Vec<TextManipulation>>,
+    /// XPath based changes of xml bodies, applied after `text`
+    pub xml: Option<Vec<XmlManipulation>>,
+    /// RFC 7386 merge patch applied to the response body
+    #[serde(rename = "mergePatch")]
+    pub merge_patch: Option<Value>,
+    /// RFC 6902 operations applied to the response body
+    #[schemars(with = "Option<Vec<Value>>")]
+    pub patch: Option<json_patch::Patch>,
+    /// jq filter run over the response body after all other modifications
+    pub transform: Option<String>,
     pub status: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifyResponseProxy {
+    /// Replace headers of the same name
     #[serde(rename = "setHeaders")]
-    pub add_headers: Option<HashMap<String, String>>,
+    pub add_headers: Option<HashMap<String, HeaderValues>>,
+    /// Add to headers of the same name
+    #[serde(rename = "appendHeaders")]
+    pub append_headers: Option<HashMap<String, HeaderValues>>,
     #[serde(rename = "keepHeaders")]
     pub delete_headers: Option<Vec<String>>,
+    #[serde(rename = "setCookies")]
+    pub set_cookies: Option<Vec<SetCookie>>,
+    /// Cookies the client is told to remove
+    #[serde(rename = "deleteCookies")]
+    pub delete_cookies: Option<Vec<DeleteCookie>>,
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ForwardUri {
+    Single(String),
+    Balanced(Vec<Upstream>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Upstream {
+    pub uri: String,
+    /// Relative weight, only used by the `Weighted` strategy
+    pub weight: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoadBalancing {
+    pub strategy: Option<BalancingStrategy>,
+    #[serde(rename = "healthCheck")]
+    pub health_check: Option<HealthCheck>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub enum BalancingStrategy {
+    #[default]
+    RoundRobin,
+    Random,
+    Weighted,
+    FirstHealthy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub enum BodyEncoding {
+    #[default]
+    Text,
+    Base64,
+}
+
+/// Answers with 429 once a client used up its requests
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimit {
+    /// Requests per window, also the size of the bucket
+    pub limit: u64,
+    /// Milliseconds until a window resets or an empty bucket is full again
+    pub window: u64,
+    pub strategy: Option<RateLimitStrategy>,
+    /// What requests are counted by, the client ip if not given. Requests
+    /// without the header or query parameter share one quota
+    pub key: Option<RateLimitKey>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub enum RateLimitStrategy {
+    #[default]
+    FixedWindow,
+    TokenBucket,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum RateLimitKey {
+    Ip,
+    Header(String),
+    /// An api key sent as query parameter
+    Query(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HealthCheck {
+    /// Path requested on every upstream, a 2xx answer marks it healthy
+    pub path: String,
+    /// Milliseconds between two checks
+    pub interval: Option<u64>,
+}
+
+/// Served instead of the upstream response when the upstream is unreachable
+/// or answers with a server error
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Fallback {
+    /// Skip the mock and continue with the next matching rule
+    #[serde(rename = "nextRule")]
+    pub next_rule: Option<bool>,
+    pub body: Option<Value>,
     pub status: Option<String>,
+    pub headers: Option<HashMap<String, HeaderValues>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebSocketReply {
+    /// Regex for incoming text messages
+    pub matches: String,
+    pub send: Option<Vec<Value>>,
+    /// Close the connection after sending the reply
+    pub close: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebSocketPush {
+    /// Interval in ms
+    pub every: u64,
+    pub send: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebSocketClose {
+    /// Close code, 1000 by default
+    pub code: Option<u16>,
+    pub reason: Option<String>,
+    /// Close the connection this many ms after it was established
+    pub after: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ServerSentEvent {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    /// Strings are sent as they are, other values as json
+    pub data: Option<Value>,
+    /// Reconnection time in ms for the client
+    pub retry: Option<u64>,
+    /// Wait this many ms before sending the event
+    pub delay: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -93,6 +436,36 @@ pub struct BodyManipulation {
     pub with: Value,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TextManipulation {
+    /// Regex whose matches are replaced, `with` may refer to groups as `$1`
+    pub replace: Option<String>,
+    /// Insert `with` in front of the first occurrence of this text
+    pub before: Option<String>,
+    /// Insert `with` behind the first occurrence of this text
+    pub after: Option<String>,
+    /// Insert `with` as first child of this html element, e.g. `head`
+    #[serde(rename = "prependTo")]
+    pub prepend_to: Option<String>,
+    /// Insert `with` as last child of this html element, e.g. `body`
+    #[serde(rename = "appendTo")]
+    pub append_to: Option<String>,
+    pub with: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct XmlManipulation {
+    /// XPath selecting the nodes to change
+    pub at: String,
+    /// Set the text of the selected elements or the value of attributes
+    pub set: Option<String>,
+    /// Replace the selected elements with this xml fragment
+    pub replace: Option<String>,
+    pub remove: Option<bool>,
+    /// Prefixes usable in the xpath, `soap` and `soap12` are predefined
+    pub namespaces: Option<HashMap<String, String>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Plugin {
     pub path: String,
@@ -103,6 +476,7 @@ pub struct Plugin {
 #[derive(Deserialize, Clone, Debug, JsonSchema)]
 pub struct Config {
     pub active_rule_indices: Vec<usize>,
+    #[cfg(feature = "ui")]
     pub fe_selected_rule: usize,
     pub rules: Vec<RuleSet>,
 }
@@ -111,6 +485,7 @@ impl Default for Config {
     fn default() -> Self {
         Config {
             active_rule_indices: vec![0],
+            #[cfg(feature = "ui")]
             fe_selected_rule: 0,
             rules: vec![RuleSet::Rule(Rule {
                 name: String::from("Static fallback - no rules found"),
@@ -120,8 +495,20 @@ impl Default for Config {
                         uri: String::from(".*"),
                         body: None,
                     }],
+                    matches_host: None,
                     matches_methods: None,
                     body_contains: None,
+                    matches_xpath: None,
+                    matches_soap_action: None,
+                    matches_graphql: None,
+                    matches_form: None,
+                    matches_files: None,
+                    matches_cookies: None,
+                    matches_jwt: None,
+                    times: None,
+                    after_hits: None,
+                    active_from: None,
+                    active_until: None,
                 },
                 then: Then::Static {
                     static_base_dir: Some(
@@ -133,7 +520,9 @@ impl Default for Config {
                     ),
                 },
                 with: None,
+                auth: None,
                 path: String::from(""),
+                state: RuleState::default(),
             })],
         }
     }
@@ -149,9 +538,9 @@ impl Config {
             return Ok(Config::default());
         }
 
-        //load plugins
+        //load plugins and set up runtime state
         //TODO: error handling here, else one faulty plugin block destroys the whole config
-        for rule in &mut rules {
+        for (position, rule) in rules.iter_mut().enumerate() {
             match rule {
                 RuleSet::Rule(rule) => {
                     if let Some(with) = &rule.with {
@@ -172,18 +561,29 @@ impl Config {
                             }
                         }
                     }
+                    rule.state = RuleState::new(rule, position).map_err(
+                        |source| DeserializationError::Rule {
+                            name: rule.name.clone(),
+                            source,
+                        },
+                    )?;
                 }
             }
         }
         Ok(Config {
             //all rules are active initially
             active_rule_indices: (0..rules.len()).collect(),
+            #[cfg(feature = "ui")]
             fe_selected_rule: 0,
             rules,
         })
     }
+}
 
-    pub fn reload(&mut self, paths: &[PathBuf]) -> Result<()> {
+// rule selection and reloading are driven by the terminal ui
+#[cfg(feature = "ui")]
+impl Config {
+    pub fn reload(&mut self, paths: &[PathBuf]) -> eyre::Result<()> {
         //TODO enable plugin reload
         match Config::load(paths) {
             Ok(new_config) => {
@@ -192,7 +592,7 @@ impl Config {
                 self.fe_selected_rule = new_config.fe_selected_rule;
                 Ok(())
             }
-            Err(e) => Err(eyre!("Error reloading config: {e:?}")),
+            Err(e) => Err(eyre::eyre!("Error reloading config: {e:?}")),
         }
     }
 
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use eyre::Result;
+use http::{
+    header::{COOKIE, SET_COOKIE},
+    HeaderMap, HeaderValue,
+};
+use regex::Regex;
+use serde_json::Value;
+
+use super::configuration::{DeleteCookie, SameSite, SetCookie};
+use super::rule::error::ConfigurationError;
+use super::template;
+
+// cookies sent with a request, by name
+pub fn parse(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let value = value.trim().trim_matches('"');
+            Some((name.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+// the value regexes of `matchesCookies`, compiled when the rule is loaded
+pub fn patterns(
+    cookies: &HashMap<String, String>,
+) -> Result<Vec<(String, Regex)>, ConfigurationError> {
+    cookies
+        .iter()
+        .map(|(name, pattern)| Ok((name.clone(), Regex::new(pattern)?)))
+        .collect()
+}
+
+// every cookie has to be present with a value matching its regex
+pub fn matches(headers: &HeaderMap, cookies: &[(String, Regex)]) -> bool {
+    let sent = parse(headers);
+    cookies.iter().all(|(name, regex)| {
+        sent.get(name).is_some_and(|value| regex.is_match(value))
+    })
+}
+
+// every cookie gets a Set-Cookie header of its own, values are rendered
+// when a request context is given
+pub fn apply(
+    headers: &mut HeaderMap,
+    set_cookies: Option<&Vec<SetCookie>>,
+    delete_cookies: Option<&Vec<DeleteCookie>>,
+    context: Option<&Value>,
+) -> Result<()> {
+    for cookie in set_cookies.into_iter().flatten() {
+        let value = match context {
+            Some(context) => template::render_str(&cookie.value, context),
+            None => cookie.value.clone(),
+        };
+        headers.append(
+            SET_COOKIE,
+            HeaderValue::from_str(&header_value(cookie, &value))?,
+        );
+    }
+    for cookie in delete_cookies.into_iter().flatten() {
+        headers.append(SET_COOKIE, HeaderValue::from_str(&deletion(cookie))?);
+    }
+    Ok(())
+}
+
+// browsers only remove a cookie with the path and domain it was set for
+fn deletion(cookie: &DeleteCookie) -> String {
+    let (name, path, domain) = match cookie {
+        DeleteCookie::Name(name) => (name, None, None),
+        DeleteCookie::Cookie { name, path, domain } => {
+            (name, path.as_deref(), domain.as_deref())
+        }
+    };
+    let mut header = format!("{name}=; Path={}", path.unwrap_or("/"));
+    if let Some(domain) = domain {
+        header += &format!("; Domain={domain}");
+    }
+    header + "; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+}
+
+fn header_value(cookie: &SetCookie, value: &str) -> String {
+    let mut header = format!("{}={value}", cookie.name);
+    if let Some(path) = &cookie.path {
+        header += &format!("; Path={path}");
+    }
+    if let Some(domain) = &cookie.domain {
+        header += &format!("; Domain={domain}");
+    }
+    if let Some(max_age) = cookie.max_age {
+        header += &format!("; Max-Age={max_age}");
+    }
+    if let Some(same_site) = &cookie.same_site {
+        header += match same_site {
+            SameSite::Strict => "; SameSite=Strict",
+            SameSite::Lax => "; SameSite=Lax",
+            SameSite::None => "; SameSite=None",
+        };
+    }
+    if cookie.secure.unwrap_or(false) {
+        header += "; Secure";
+    }
+    if cookie.http_only.unwrap_or(false) {
+        header += "; HttpOnly";
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    fn set_cookies(
+        set: &str,
+        delete: &str,
+        context: Option<&Value>,
+    ) -> Vec<String> {
+        let set: Vec<SetCookie> = serde_yaml::from_str(set).unwrap();
+        let delete: Vec<DeleteCookie> = serde_yaml::from_str(delete).unwrap();
+        let mut headers = HeaderMap::new();
+        apply(&mut headers, Some(&set), Some(&delete), context).unwrap();
+        headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn request_cookies_are_parsed() {
+        let sent = parse(&request("session=abc; theme=\"dark\";flag=1"));
+        assert_eq!(sent["session"], "abc");
+        assert_eq!(sent["theme"], "dark");
+        assert_eq!(sent["flag"], "1");
+    }
+
+    #[test]
+    fn every_cookie_has_to_match() {
+        let cookies = HashMap::from([
+            (String::from("session"), String::from("^[a-z]+$")),
+            (String::from("theme"), String::from("dark")),
+        ]);
+        let patterns = patterns(&cookies).unwrap();
+        assert!(matches(&request("session=abc; theme=dark"), &patterns));
+        assert!(!matches(&request("session=abc"), &patterns));
+        assert!(!matches(&request("session=42; theme=dark"), &patterns));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let cookies = HashMap::from([(String::from("a"), String::from("("))]);
+        assert!(patterns(&cookies).is_err());
+    }
+
+    #[test]
+    fn cookies_are_set_with_their_attributes() {
+        let set = "- name: session\n  value: '{{request.query.id}}'\n  \
+            path: /app\n  domain: example.com\n  maxAge: 60\n  \
+            sameSite: Strict\n  secure: true\n  httpOnly: true";
+        let context =
+            serde_json::json!({ "request": { "query": { "id": 7 } } });
+        assert_eq!(
+            set_cookies(set, "[]", Some(&context)),
+            ["session=7; Path=/app; Domain=example.com; Max-Age=60; \
+                SameSite=Strict; Secure; HttpOnly"]
+        );
+        assert_eq!(
+            set_cookies(set, "[]", None)[0],
+            "session={{request.query.id}}; Path=/app; Domain=example.com; \
+                Max-Age=60; SameSite=Strict; Secure; HttpOnly"
+        );
+    }
+
+    #[test]
+    fn deleted_cookies_keep_their_path_and_domain() {
+        let delete = "- old\n- name: session\n  path: /app\n  \
+            domain: example.com";
+        assert_eq!(
+            set_cookies("[]", delete, None),
+            [
+                "old=; Path=/; Max-Age=0; \
+                    Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+                "session=; Path=/app; Domain=example.com; Max-Age=0; \
+                    Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            ]
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, HeaderMap};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::configuration::FileMatch;
+use super::rule::{error::ConfigurationError, Rule};
+
+/// Structured view of an urlencoded or multipart request body
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Form {
+    pub fields: HashMap<String, Vec<String>>,
+    pub files: Vec<UploadedFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadedFile {
+    pub field: String,
+    #[serde(rename = "fileName")]
+    pub file_name: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    pub size: u64,
+}
+
+// regexes of `matchesForm` and `matchesFiles`, compiled when the rule is
+// loaded
+#[derive(Debug)]
+pub struct FormPatterns {
+    fields: Option<Vec<(String, Regex)>>,
+    files: Vec<(FileMatch, FilePatterns)>,
+}
+
+#[derive(Debug)]
+pub struct FilePatterns {
+    file_name: Option<Regex>,
+    content_type: Option<Regex>,
+}
+
+pub fn load_rule(
+    rule: &Rule,
+) -> Result<Option<FormPatterns>, ConfigurationError> {
+    let (fields, files) = (&rule.when.matches_form, &rule.when.matches_files);
+    if fields.is_none() && files.is_none() {
+        return Ok(None);
+    }
+
+    let fields = fields
+        .as_ref()
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|(name, pattern)| Ok((name.clone(), Regex::new(pattern)?)))
+                .collect::<Result<_, regex::Error>>()
+        })
+        .transpose()?;
+    let files = files
+        .iter()
+        .flatten()
+        .map(|file_match| {
+            let regex = |pattern: &Option<String>| {
+                pattern.as_deref().map(Regex::new).transpose()
+            };
+            let patterns = FilePatterns {
+                file_name: regex(&file_match.file_name)?,
+                content_type: regex(&file_match.content_type)?,
+            };
+            Ok((file_match.clone(), patterns))
+        })
+        .collect::<Result<_, ConfigurationError>>()?;
+    Ok(Some(FormPatterns { fields, files }))
+}
+
+impl FormPatterns {
+    // requests without a form body never match
+    pub fn matches(&self, form: Option<&Form>) -> bool {
+        match form {
+            Some(form) => {
+                self.fields
+                    .as_ref()
+                    .is_none_or(|fields| form.matches_fields(fields))
+                    && self.files.iter().all(|(file_match, patterns)| {
+                        form.matches_file(file_match, patterns)
+                    })
+            }
+            None => false,
+        }
+    }
+}
+
+impl Form {
+    pub async fn parse(headers: &HeaderMap, body: &Bytes) -> Option<Form> {
+        let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+        let mime = content_type.split(';').next()?.trim().to_lowercase();
+
+        match mime.as_str() {
+            "application/x-www-form-urlencoded" => {
+                let mut form = Form::default();
+                for (key, value) in form_urlencoded::parse(body) {
+                    form.push_field(key.into_owned(), value.into_owned());
+                }
+                Some(form)
+            }
+            "multipart/form-data" => {
+                let boundary = multer::parse_boundary(content_type).ok()?;
+                match Form::parse_multipart(body.clone(), boundary).await {
+                    Ok(form) => Some(form),
+                    Err(e) => {
+                        log::info!("could not parse multipart body: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    async fn parse_multipart(
+        body: Bytes,
+        boundary: String,
+    ) -> Result<Form, multer::Error> {
+        let stream =
+            futures::stream::once(async move { Ok::<_, Infallible>(body) });
+        let mut multipart = multer::Multipart::new(stream, boundary);
+        let mut form = Form::default();
+
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+            let file_name = field.file_name().map(String::from);
+            let content_type = field.content_type().map(|m| m.to_string());
+
+            // parts without a file name are plain fields
+            if file_name.is_none() {
+                let text = field.text().await?;
+                form.push_field(name, text);
+            } else {
+                let size = field.bytes().await?.len() as u64;
+                form.files.push(UploadedFile {
+                    field: name,
+                    file_name,
+                    content_type,
+                    size,
+                });
+            }
+        }
+        Ok(form)
+    }
+
+    fn push_field(&mut self, name: String, value: String) {
+        self.fields.entry(name).or_default().push(value);
+    }
+
+    // every field has to be present with at least one value matching its regex
+    fn matches_fields(&self, fields: &[(String, Regex)]) -> bool {
+        fields.iter().all(|(name, regex)| {
+            self.fields.get(name).is_some_and(|values| {
+                values.iter().any(|value| regex.is_match(value))
+            })
+        })
+    }
+
+    fn matches_file(
+        &self,
+        file_match: &FileMatch,
+        patterns: &FilePatterns,
+    ) -> bool {
+        self.files.iter().any(|file| {
+            let matches = |regex: &Option<Regex>, value: &Option<String>| {
+                regex.as_ref().is_none_or(|regex| {
+                    value.as_deref().is_some_and(|v| regex.is_match(v))
+                })
+            };
+            file_match.field.as_ref().is_none_or(|f| f == &file.field)
+                && matches(&patterns.file_name, &file.file_name)
+                && matches(&patterns.content_type, &file.content_type)
+                && file_match.min_size.is_none_or(|min| file.size >= min)
+                && file_match.max_size.is_none_or(|max| file.size <= max)
+        })
+    }
+
+    // single values are exposed as strings, repeated ones as arrays
+    pub fn to_value(&self) -> Value {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, values)| {
+                let value = match values.as_slice() {
+                    [single] => Value::String(single.clone()),
+                    _ => Value::from(values.clone()),
+                };
+                (name.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        serde_json::json!({
+            "fields": fields,
+            "files": serde_json::to_value(&self.files).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::intermediary::Intermediary;
+    use http::{HeaderValue, Method};
+
+    const MULTIPART: &str = "--XX\r\n\
+        Content-Disposition: form-data; name=\"user\"\r\n\r\n\
+        alice\r\n\
+        --XX\r\n\
+        Content-Disposition: form-data; name=\"avatar\"; \
+        filename=\"me.png\"\r\n\
+        Content-Type: image/png\r\n\r\n\
+        12345\r\n\
+        --XX--\r\n";
+
+    async fn form(content_type: &'static str, body: &str) -> Option<Form> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        Form::parse(&headers, &Bytes::from(body.to_string())).await
+    }
+
+    fn rule(when: &str) -> Result<Rule, ConfigurationError> {
+        Rule::from_yaml(&format!(
+            "name: form\nwhen:\n  matchesUris:\n    - uri: ^/\n{when}\
+             then:\n  functionAs: Mock\n  body: ok\n"
+        ))
+    }
+
+    fn applies(rule: &Rule, form: Option<Form>) -> bool {
+        let mut request = Intermediary::request(Method::POST, "/", b"");
+        request.form = form;
+        rule.should_apply(&request).is_ok()
+    }
+
+    #[tokio::test]
+    async fn urlencoded_fields_are_collected() {
+        let form = form("application/x-www-form-urlencoded", "a=1&a=2&b=x%20y")
+            .await
+            .unwrap();
+        assert_eq!(form.fields["a"], ["1", "2"]);
+        assert_eq!(
+            form.to_value()["fields"],
+            serde_json::json!({"a": ["1", "2"], "b": "x y"})
+        );
+    }
+
+    #[tokio::test]
+    async fn multipart_parts_are_fields_or_files() {
+        let form = form("multipart/form-data; boundary=XX", MULTIPART)
+            .await
+            .unwrap();
+        assert_eq!(form.fields["user"], ["alice"]);
+        let file = &form.files[0];
+        assert_eq!(file.field, "avatar");
+        assert_eq!(file.file_name.as_deref(), Some("me.png"));
+        assert_eq!(file.content_type.as_deref(), Some("image/png"));
+        assert_eq!(file.size, 5);
+    }
+
+    #[tokio::test]
+    async fn other_bodies_are_no_forms() {
+        assert!(form("application/json", "{}").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fields_have_to_match() {
+        let form = form("application/x-www-form-urlencoded", "a=1&a=2").await;
+        assert!(applies(
+            &rule("  matchesForm:\n    a: ^2$\n").unwrap(),
+            form.clone()
+        ));
+        assert!(!applies(
+            &rule("  matchesForm:\n    a: ^3$\n").unwrap(),
+            form.clone()
+        ));
+        assert!(!applies(
+            &rule("  matchesForm:\n    b: ''\n").unwrap(),
+            form
+        ));
+        assert!(!applies(
+            &rule("  matchesForm:\n    a: ''\n").unwrap(),
+            None
+        ));
+    }
+
+    #[tokio::test]
+    async fn files_have_to_match() {
+        let form = form("multipart/form-data; boundary=XX", MULTIPART).await;
+        let matching = rule(
+            "  matchesFiles:\n    - field: avatar\n      \
+             fileName: \\.png$\n      contentType: ^image/\n      \
+             maxSize: 5\n",
+        )
+        .unwrap();
+        assert!(applies(&matching, form.clone()));
+        let too_small = rule("  matchesFiles:\n    - minSize: 6\n").unwrap();
+        assert!(!applies(&too_small, form.clone()));
+        let other_type =
+            rule("  matchesFiles:\n    - contentType: ^text/\n").unwrap();
+        assert!(!applies(&other_type, form));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(rule("  matchesForm:\n    a: '('\n").is_err());
+        assert!(rule("  matchesFiles:\n    - fileName: '('\n").is_err());
+    }
+}
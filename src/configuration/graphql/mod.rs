@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+
+use graphql_parser::query::{
+    self, Definition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition,
+};
+use graphql_parser::schema::{self, Type, TypeDefinition};
+use json_dotpath::DotPaths;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use super::configuration::GraphQLMatch;
+use super::intermediary::Intermediary;
+use super::rule::error::ConfigurationError;
+use super::rule::then::Then;
+use super::rule::Rule;
+
+// number of items generated for lists without resolver
+const MOCKED_LIST_LENGTH: usize = 2;
+
+/// The `schemaFile` of a rule, parsed when the rule is loaded
+pub type Schema = schema::Document<'static, String>;
+
+pub fn load_rule(rule: &Rule) -> Result<Option<Schema>, ConfigurationError> {
+    let Then::GraphQL {
+        schema_file: Some(file),
+        ..
+    } = &rule.then
+    else {
+        return Ok(None);
+    };
+    let path = rule.relative_path(file);
+    let invalid = |e: &dyn std::fmt::Display| {
+        ConfigurationError::GraphQL(format!("{}: {e}", path.display()))
+    };
+    let schema = std::fs::read_to_string(&path).map_err(|e| invalid(&e))?;
+    let schema = schema::parse_schema::<String>(&schema)
+        .map_err(|e| invalid(&e))?
+        .into_static();
+    Ok(Some(schema))
+}
+
+/// The regexes of `matchesGraphQL`, compiled when the rule is loaded
+#[derive(Debug)]
+pub struct GraphQLPatterns {
+    operation_name: Option<Regex>,
+    operation_type: Option<String>,
+    variables: Vec<(String, Regex)>,
+}
+
+impl GraphQLPatterns {
+    pub fn new(
+        graphql_match: &GraphQLMatch,
+    ) -> Result<GraphQLPatterns, ConfigurationError> {
+        Ok(GraphQLPatterns {
+            operation_name: graphql_match
+                .operation_name
+                .as_deref()
+                .map(Regex::new)
+                .transpose()?,
+            operation_type: graphql_match.operation_type.clone(),
+            variables: graphql_match
+                .variables
+                .iter()
+                .flatten()
+                .map(|(path, pattern)| Ok((path.clone(), Regex::new(pattern)?)))
+                .collect::<Result<_, ConfigurationError>>()?,
+        })
+    }
+}
+
+/// The operation of a graphql request, sent as json body or in the query
+/// string of a GET request
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub name: Option<String>,
+    pub kind: &'static str,
+    pub query: String,
+    pub variables: Value,
+    pub root_fields: Vec<String>,
+}
+
+impl Operation {
+    pub fn from_request(intermediary: &Intermediary) -> Option<Operation> {
+        let (query, name, variables) = match &intermediary.body {
+            Value::Object(body) => (
+                body.get("query")?.as_str()?.to_string(),
+                body.get("operationName")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                body.get("variables").cloned().unwrap_or_default(),
+            ),
+            _ => {
+                let params: HashMap<String, String> = form_urlencoded::parse(
+                    intermediary.uri.as_ref()?.query()?.as_bytes(),
+                )
+                .into_owned()
+                .collect();
+                (
+                    params.get("query")?.clone(),
+                    params.get("operationName").cloned(),
+                    params
+                        .get("variables")
+                        .and_then(|v| serde_json::from_str(v).ok())
+                        .unwrap_or_default(),
+                )
+            }
+        };
+
+        let document = query::parse_query::<String>(&query).ok()?;
+        let (kind, operation_name, selection_set) = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                Definition::Operation(operation) => Some(describe(operation)),
+                Definition::Fragment(_) => None,
+            })
+            .find(|(_, operation_name, _)| {
+                name.is_none() || name.as_ref() == *operation_name
+            })?;
+        let root_fields = selection_set
+            .items
+            .iter()
+            .filter_map(|selection| match selection {
+                Selection::Field(field) => Some(field.name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Some(Operation {
+            name: name.or(operation_name.cloned()),
+            kind,
+            query: query.clone(),
+            variables,
+            root_fields,
+        })
+    }
+
+    pub fn matches(&self, patterns: &GraphQLPatterns) -> bool {
+        if let Some(regex) = &patterns.operation_name {
+            if !self.name.as_deref().is_some_and(|n| regex.is_match(n)) {
+                return false;
+            }
+        }
+        if let Some(kind) = &patterns.operation_type {
+            if !kind.eq_ignore_ascii_case(self.kind) {
+                return false;
+            }
+        }
+        patterns.variables.iter().all(|(path, regex)| {
+            let value = self.variables.dot_get::<Value>(path).ok().flatten();
+            match value {
+                Some(Value::String(value)) => regex.is_match(&value),
+                Some(value) => regex.is_match(&value.to_string()),
+                None => false,
+            }
+        })
+    }
+
+    pub fn root_type(&self) -> &'static str {
+        match self.kind {
+            "mutation" => "Mutation",
+            "subscription" => "Subscription",
+            _ => "Query",
+        }
+    }
+
+    // answered by the rule instead of an upstream
+    pub fn is_mocked(
+        &self,
+        data: Option<&HashMap<String, Value>>,
+        resolvers: Option<&HashMap<String, Value>>,
+    ) -> bool {
+        let has_data = self
+            .name
+            .as_ref()
+            .is_some_and(|name| data.is_some_and(|d| d.contains_key(name)));
+        let has_resolver = resolvers.is_some_and(|resolvers| {
+            self.root_fields.iter().any(|field| {
+                resolvers.contains_key(&format!("{}.{field}", self.root_type()))
+            })
+        });
+        has_data || has_resolver
+    }
+}
+
+fn describe<'o, 'a>(
+    operation: &'o OperationDefinition<'a, String>,
+) -> (&'static str, Option<&'o String>, &'o SelectionSet<'a, String>) {
+    match operation {
+        OperationDefinition::SelectionSet(set) => ("query", None, set),
+        OperationDefinition::Query(q) => {
+            ("query", q.name.as_ref(), &q.selection_set)
+        }
+        OperationDefinition::Mutation(m) => {
+            ("mutation", m.name.as_ref(), &m.selection_set)
+        }
+        OperationDefinition::Subscription(s) => {
+            ("subscription", s.name.as_ref(), &s.selection_set)
+        }
+    }
+}
+
+struct Executor<'e, 'a> {
+    types: HashMap<&'e str, &'e TypeDefinition<'a, String>>,
+    // object types in schema order, to pick implementations deterministically
+    objects: Vec<&'e schema::ObjectType<'a, String>>,
+    root_type: String,
+    default_root_type: &'static str,
+    fragments: HashMap<&'e str, &'e query::FragmentDefinition<'a, String>>,
+    resolvers: &'e HashMap<String, Value>,
+}
+
+// answers the operation with the resolvers, shaped by the schema if there is
+// one. Fields without resolver get generated values of their type
+pub fn execute(
+    operation: &Operation,
+    schema: Option<&Schema>,
+    resolvers: &HashMap<String, Value>,
+) -> Result<Value, ConfigurationError> {
+    // shares the lifetime of the schema
+    let document = query::parse_query::<String>(&operation.query)
+        .map_err(|e| ConfigurationError::GraphQL(e.to_string()))?
+        .into_static();
+
+    let mut root_type = operation.root_type().to_string();
+    let mut types = HashMap::new();
+    let mut objects = Vec::new();
+    for definition in schema.iter().flat_map(|s| &s.definitions) {
+        match definition {
+            schema::Definition::TypeDefinition(definition) => {
+                types.insert(type_name(definition), definition);
+                if let TypeDefinition::Object(object) = definition {
+                    objects.push(object);
+                }
+            }
+            schema::Definition::SchemaDefinition(roots) => {
+                let root = match operation.kind {
+                    "mutation" => &roots.mutation,
+                    "subscription" => &roots.subscription,
+                    _ => &roots.query,
+                };
+                if let Some(root) = root {
+                    root_type = root.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut fragments = HashMap::new();
+    let mut selection_set = None;
+    for definition in &document.definitions {
+        match definition {
+            Definition::Fragment(fragment) => {
+                fragments.insert(fragment.name.as_str(), fragment);
+            }
+            Definition::Operation(definition) => {
+                let (_, name, set) = describe(definition);
+                if operation.name.is_none() || name == operation.name.as_ref()
+                {
+                    selection_set = selection_set.or(Some(set));
+                }
+            }
+        }
+    }
+    let selection_set = selection_set.ok_or_else(|| {
+        ConfigurationError::GraphQL(String::from("operation not found"))
+    })?;
+
+    let executor = Executor {
+        types,
+        objects,
+        root_type,
+        default_root_type: operation.root_type(),
+        fragments,
+        resolvers,
+    };
+    Ok(Value::Object(executor.select(
+        &executor.root_type,
+        selection_set,
+        None,
+    )))
+}
+
+fn type_name<'e>(definition: &'e TypeDefinition<String>) -> &'e str {
+    match definition {
+        TypeDefinition::Scalar(t) => &t.name,
+        TypeDefinition::Object(t) => &t.name,
+        TypeDefinition::Interface(t) => &t.name,
+        TypeDefinition::Union(t) => &t.name,
+        TypeDefinition::Enum(t) => &t.name,
+        TypeDefinition::InputObject(t) => &t.name,
+    }
+}
+
+impl<'e, 'a> Executor<'e, 'a> {
+    fn select(
+        &self,
+        type_name: &str,
+        selection_set: &SelectionSet<'a, String>,
+        source: Option<&Value>,
+    ) -> Map<String, Value> {
+        let mut selected = Map::new();
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    let key = field.alias.as_ref().unwrap_or(&field.name);
+                    let value = if field.name == "__typename" {
+                        Value::String(type_name.to_string())
+                    } else {
+                        self.resolve(type_name, field, source)
+                    };
+                    selected.insert(key.clone(), value);
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) =
+                        self.fragments.get(spread.fragment_name.as_str())
+                    {
+                        if self.applies(type_name, &fragment.type_condition) {
+                            selected.extend(self.select(
+                                type_name,
+                                &fragment.selection_set,
+                                source,
+                            ));
+                        }
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let applies = fragment
+                        .type_condition
+                        .as_ref()
+                        .is_none_or(|cond| self.applies(type_name, cond));
+                    if applies {
+                        selected.extend(self.select(
+                            type_name,
+                            &fragment.selection_set,
+                            source,
+                        ));
+                    }
+                }
+            }
+        }
+        selected
+    }
+
+    fn applies(
+        &self,
+        type_name: &str,
+        condition: &TypeCondition<String>,
+    ) -> bool {
+        let TypeCondition::On(condition) = condition;
+        condition == type_name
+            || self.possible_types(condition).contains(&type_name)
+    }
+
+    fn resolve(
+        &self,
+        type_name: &str,
+        field: &query::Field<'a, String>,
+        source: Option<&Value>,
+    ) -> Value {
+        // resolvers of the root type can use its default name as well
+        let resolver = |type_name: &str| {
+            self.resolvers.get(&format!("{type_name}.{}", field.name))
+        };
+        let value = source
+            .and_then(|source| source.get(&field.name))
+            .or_else(|| resolver(type_name))
+            .or_else(|| {
+                (type_name == self.root_type)
+                    .then(|| resolver(self.default_root_type))
+                    .flatten()
+            });
+        let field_type = self.field_type(type_name, &field.name);
+        match field_type {
+            Some(field_type) => self.complete(field_type, field, value),
+            None => self.project(field, value),
+        }
+    }
+
+    // without schema the resolved value is taken as it is
+    fn project(
+        &self,
+        field: &query::Field<'a, String>,
+        value: Option<&Value>,
+    ) -> Value {
+        let has_selection = !field.selection_set.items.is_empty();
+        match value {
+            Some(Value::Array(items)) if has_selection => Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.project(field, Some(item)))
+                    .collect(),
+            ),
+            Some(Value::Object(_)) if has_selection => {
+                let type_name = value
+                    .and_then(|v| v.get("__typename"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                Value::Object(self.select(
+                    type_name,
+                    &field.selection_set,
+                    value,
+                ))
+            }
+            Some(value) => value.clone(),
+            None => Value::Null,
+        }
+    }
+
+    fn complete(
+        &self,
+        field_type: &Type<'a, String>,
+        field: &query::Field<'a, String>,
+        value: Option<&Value>,
+    ) -> Value {
+        match (field_type, value) {
+            (_, Some(Value::Null)) => Value::Null,
+            (Type::NonNullType(inner), _) => self.complete(inner, field, value),
+            (Type::ListType(inner), Some(Value::Array(items))) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.complete(inner, field, Some(item)))
+                    .collect(),
+            ),
+            (Type::ListType(inner), None) => Value::Array(
+                (0..MOCKED_LIST_LENGTH)
+                    .map(|_| self.complete(inner, field, None))
+                    .collect(),
+            ),
+            (Type::ListType(inner), Some(item)) => {
+                Value::Array(vec![self.complete(inner, field, Some(item))])
+            }
+            (Type::NamedType(name), _) => {
+                self.complete_named(name, field, value)
+            }
+        }
+    }
+
+    fn complete_named(
+        &self,
+        name: &str,
+        field: &query::Field<'a, String>,
+        value: Option<&Value>,
+    ) -> Value {
+        match self.types.get(name) {
+            Some(TypeDefinition::Object(_)) => Value::Object(self.select(
+                name,
+                &field.selection_set,
+                value,
+            )),
+            Some(TypeDefinition::Interface(_) | TypeDefinition::Union(_)) => {
+                let concrete = value
+                    .and_then(|v| v.get("__typename"))
+                    .and_then(Value::as_str)
+                    .or_else(|| self.possible_types(name).first().copied())
+                    .unwrap_or(name);
+                Value::Object(self.select(
+                    concrete,
+                    &field.selection_set,
+                    value,
+                ))
+            }
+            Some(TypeDefinition::Enum(enum_type)) => match value {
+                Some(value) => value.clone(),
+                None => enum_type
+                    .values
+                    .first()
+                    .map_or(Value::Null, |v| Value::String(v.name.clone())),
+            },
+            _ => match value {
+                Some(value) => value.clone(),
+                None => match name {
+                    "Int" => Value::from(1),
+                    "Float" => Value::from(1.5),
+                    "Boolean" => Value::Bool(true),
+                    "ID" => Value::String(String::from("1")),
+                    _ => Value::String(field.name.clone()),
+                },
+            },
+        }
+    }
+
+    fn field_type(
+        &self,
+        type_name: &str,
+        field_name: &str,
+    ) -> Option<&'e Type<'a, String>> {
+        let fields = match self.types.get(type_name)? {
+            TypeDefinition::Object(t) => &t.fields,
+            TypeDefinition::Interface(t) => &t.fields,
+            _ => return None,
+        };
+        fields
+            .iter()
+            .find(|field| field.name == field_name)
+            .map(|field| &field.field_type)
+    }
+
+    // object types implementing an interface or belonging to a union
+    fn possible_types(&self, name: &str) -> Vec<&'e str> {
+        match self.types.get(name) {
+            Some(TypeDefinition::Union(union)) => {
+                union.types.iter().map(String::as_str).collect()
+            }
+            Some(TypeDefinition::Interface(_)) => self
+                .objects
+                .iter()
+                .filter(|object| {
+                    object.implements_interfaces.iter().any(|i| i == name)
+                })
+                .map(|object| object.name.as_str())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::super::rule::state::RuleState;
+    use super::*;
+
+    const RULE: &str = r#"
+name: graphql
+when:
+  matchesUris:
+    - uri: ^/graphql
+  matchesGraphQL:
+    operationName: ^Get
+    operationType: query
+    variables:
+      user.id: ^4
+then:
+  functionAs: GraphQL
+  schemaFile: schema.graphql
+"#;
+
+    const SCHEMA: &str = "type Query { user(id: ID): User }\n\
+        type User { id: ID, tags: [String], admin: Boolean }";
+
+    fn operation(query: &str, variables: Value) -> Operation {
+        let body = serde_json::json!({
+            "query": query,
+            "variables": variables,
+        });
+        let request = Intermediary::request(
+            Method::POST,
+            "/graphql",
+            body.to_string().as_bytes(),
+        );
+        Operation::from_request(&request).unwrap()
+    }
+
+    // loads the rule next to a schema file, like the loader does
+    fn load(test: &str, yaml: &str) -> Result<Rule, ConfigurationError> {
+        let dir = std::env::temp_dir()
+            .join(format!("fips-graphql-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("schema.graphql"), SCHEMA).unwrap();
+        std::fs::write(dir.join("broken.graphql"), "type {").unwrap();
+        let mut rule: Rule = serde_yaml::from_str(yaml)?;
+        rule.path = dir.join("rules.yaml").to_string_lossy().into_owned();
+        let state = RuleState::new(&rule, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+        rule.state = state?;
+        Ok(rule)
+    }
+
+    #[test]
+    fn operations_are_matched_with_the_compiled_patterns() {
+        let rule = load("match", RULE).unwrap();
+        let patterns = rule.state.graphql.unwrap();
+        let query = "query GetUser($user: UserInput) { user { id } }";
+        let matching = serde_json::json!({ "user": { "id": 42 } });
+        assert!(operation(query, matching.clone()).matches(&patterns));
+        let other_id = serde_json::json!({ "user": { "id": 7 } });
+        assert!(!operation(query, other_id).matches(&patterns));
+        let mutation = "mutation GetUser { user { id } }";
+        assert!(!operation(mutation, matching.clone()).matches(&patterns));
+        let other_name = "query ListUsers { user { id } }";
+        assert!(!operation(other_name, matching).matches(&patterns));
+    }
+
+    #[test]
+    fn invalid_patterns_fail_the_rule() {
+        let yaml = RULE.replace("^Get", "(Get");
+        assert!(load("pattern", &yaml).is_err());
+    }
+
+    #[test]
+    fn schema_is_parsed_when_the_rule_is_loaded() {
+        let rule = load("schema", RULE).unwrap();
+        let schema = rule.state.schema.unwrap();
+        let operation = operation("{ user { id tags admin } }", Value::Null);
+        let resolvers = HashMap::from([(
+            String::from("Query.user"),
+            serde_json::json!({ "id": "7" }),
+        )]);
+        let data = execute(&operation, Some(&schema), &resolvers).unwrap();
+        assert_eq!(
+            data,
+            serde_json::json!({ "user": {
+                "id": "7",
+                "tags": ["tags", "tags"],
+                "admin": true,
+            }})
+        );
+    }
+
+    #[test]
+    fn missing_or_invalid_schema_fails_the_rule() {
+        let missing = RULE.replace("schema.graphql", "missing.graphql");
+        assert!(load("missing", &missing).is_err());
+        let invalid = RULE.replace("schema.graphql", "broken.graphql");
+        assert!(load("invalid", &invalid).is_err());
+    }
+
+    #[test]
+    fn without_schema_resolved_values_are_projected() {
+        let operation = operation("{ user { id } }", Value::Null);
+        let resolvers = HashMap::from([(
+            String::from("Query.user"),
+            serde_json::json!({ "id": "7", "name": "hidden" }),
+        )]);
+        let data = execute(&operation, None, &resolvers).unwrap();
+        assert_eq!(data, serde_json::json!({ "user": { "id": "7" } }));
+    }
+}
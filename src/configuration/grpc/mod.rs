@@ -0,0 +1,295 @@
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MethodDescriptor};
+use serde_json::Value;
+
+use super::rule::{error::ConfigurationError, then::Then, Rule};
+
+// status codes fips answers with on its own
+pub const INVALID_ARGUMENT: u32 = 3;
+pub const UNIMPLEMENTED: u32 = 12;
+pub const INTERNAL: u32 = 13;
+
+// `/package.Service/Method` split into service and method
+pub fn method_path(path: &str) -> Option<(&str, &str)> {
+    path.strip_prefix('/')?.split_once('/')
+}
+
+// services can be named with or without their package
+pub fn matches_method(
+    path: &str,
+    service: Option<&str>,
+    method: Option<&str>,
+) -> bool {
+    let Some((called_service, called_method)) = method_path(path) else {
+        return false;
+    };
+    service.is_none_or(|service| {
+        called_service == service
+            || called_service.rsplit('.').next() == Some(service)
+    }) && method.is_none_or(|method| called_method == method)
+}
+
+// proto files are compiled together with their imports, a descriptor set
+// adds to them
+pub fn load(
+    proto_files: &[PathBuf],
+    import_paths: &[PathBuf],
+    descriptor_set: Option<&Path>,
+) -> Result<DescriptorPool, ConfigurationError> {
+    let mut pool = if proto_files.is_empty() {
+        DescriptorPool::new()
+    } else {
+        let mut compiler = protox::Compiler::new(import_paths)
+            .map_err(|e| ConfigurationError::Grpc(e.to_string()))?;
+        compiler.include_imports(true);
+        compiler
+            .open_files(proto_files)
+            .map_err(|e| ConfigurationError::Grpc(e.to_string()))?;
+        compiler.descriptor_pool()
+    };
+
+    if let Some(descriptor_set) = descriptor_set {
+        let bytes = std::fs::read(descriptor_set).map_err(|e| {
+            ConfigurationError::Grpc(format!(
+                "{}: {e}",
+                descriptor_set.display()
+            ))
+        })?;
+        pool.decode_file_descriptor_set(bytes.as_slice())
+            .map_err(|e| ConfigurationError::Grpc(e.to_string()))?;
+    }
+    Ok(pool)
+}
+
+// descriptors of a grpc rule, files are relative to the rule file
+pub fn load_rule(
+    rule: &Rule,
+) -> Result<Option<DescriptorPool>, ConfigurationError> {
+    let Then::Grpc {
+        proto_files,
+        import_paths,
+        descriptor_set,
+        ..
+    } = &rule.then
+    else {
+        return Ok(None);
+    };
+
+    let proto_files = proto_files
+        .iter()
+        .flatten()
+        .map(|file| rule.relative_path(file))
+        .collect::<Vec<_>>();
+    let import_paths = match import_paths {
+        Some(paths) => paths.iter().map(|p| rule.relative_path(p)).collect(),
+        None => vec![rule.relative_path("")],
+    };
+    let descriptor_set = descriptor_set.as_ref().map(|s| rule.relative_path(s));
+    load(&proto_files, &import_paths, descriptor_set.as_deref()).map(Some)
+}
+
+pub fn find_method(
+    pool: &DescriptorPool,
+    path: &str,
+) -> Option<MethodDescriptor> {
+    let (service, method) = method_path(path)?;
+    pool.get_service_by_name(service)?
+        .methods()
+        .find(|m| m.name() == method)
+}
+
+// the length prefixed messages of a request body as json
+pub fn decode(
+    method: &MethodDescriptor,
+    mut body: Bytes,
+) -> Result<Vec<Value>, ConfigurationError> {
+    let mut messages = Vec::new();
+    while body.has_remaining() {
+        if body.remaining() < 5 {
+            return Err(ConfigurationError::Grpc(
+                "truncated message".to_string(),
+            ));
+        }
+        let compressed = body.get_u8();
+        let length = body.get_u32() as usize;
+        if compressed != 0 {
+            return Err(ConfigurationError::Grpc(
+                "compressed messages are not supported".to_string(),
+            ));
+        }
+        if body.remaining() < length {
+            return Err(ConfigurationError::Grpc(
+                "truncated message".to_string(),
+            ));
+        }
+        let message = DynamicMessage::decode(
+            method.input(),
+            body.split_to(length),
+        )
+        .map_err(|e| ConfigurationError::Grpc(e.to_string()))?;
+        messages.push(serde_json::to_value(&message)?);
+    }
+    Ok(messages)
+}
+
+// a json message of the method's output type, length prefixed
+pub fn encode(
+    method: &MethodDescriptor,
+    value: &Value,
+) -> Result<Bytes, ConfigurationError> {
+    let message = DynamicMessage::deserialize(method.output(), value)?;
+    let encoded = message.encode_to_vec();
+
+    let mut frame = BytesMut::with_capacity(encoded.len() + 5);
+    frame.put_u8(0);
+    frame.put_u32(encoded.len() as u32);
+    frame.put_slice(&encoded);
+    Ok(frame.freeze())
+}
+
+// grpc-message is percent encoded outside of printable ascii
+pub fn encode_message(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prost_reflect::prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+        FileDescriptorSet, MethodDescriptorProto, ServiceDescriptorProto,
+    };
+
+    fn field(
+        name: &str,
+        number: i32,
+        field_type: Type,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.into()),
+            json_name: Some(name.into()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(field_type as i32),
+            ..Default::default()
+        }
+    }
+
+    fn message(
+        name: &str,
+        fields: Vec<FieldDescriptorProto>,
+    ) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.into()),
+            field: fields,
+            ..Default::default()
+        }
+    }
+
+    // the descriptor set of `helloworld.Greeter/SayHello`, loaded the way a
+    // rule's descriptorSet is
+    fn greeter(test: &str) -> MethodDescriptor {
+        let file = FileDescriptorProto {
+            name: Some("greeter.proto".into()),
+            package: Some("helloworld".into()),
+            syntax: Some("proto3".into()),
+            message_type: vec![
+                message("HelloRequest", vec![field("name", 1, Type::String)]),
+                message(
+                    "HelloReply",
+                    vec![
+                        field("message", 1, Type::String),
+                        field("count", 2, Type::Int32),
+                    ],
+                ),
+            ],
+            service: vec![ServiceDescriptorProto {
+                name: Some("Greeter".into()),
+                method: vec![MethodDescriptorProto {
+                    name: Some("SayHello".into()),
+                    input_type: Some(".helloworld.HelloRequest".into()),
+                    output_type: Some(".helloworld.HelloReply".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let set = FileDescriptorSet { file: vec![file] };
+        let path = std::env::temp_dir()
+            .join(format!("fips-grpc-{test}-{}.bin", std::process::id()));
+        std::fs::write(&path, set.encode_to_vec()).unwrap();
+        let pool = load(&[], &[], Some(&path));
+        std::fs::remove_file(path).unwrap();
+        find_method(&pool.unwrap(), "/helloworld.Greeter/SayHello").unwrap()
+    }
+
+    #[test]
+    fn methods_match_with_or_without_package() {
+        let path = "/helloworld.Greeter/SayHello";
+        assert!(matches_method(path, None, None));
+        assert!(matches_method(path, Some("helloworld.Greeter"), None));
+        assert!(matches_method(path, Some("Greeter"), Some("SayHello")));
+        assert!(!matches_method(path, Some("Other"), None));
+        assert!(!matches_method(path, None, Some("SayBye")));
+        assert!(!matches_method("/no-method", None, None));
+    }
+
+    #[test]
+    fn messages_are_encoded_from_json() {
+        let method = greeter("encode");
+        let reply = serde_json::json!({ "message": "hi", "count": 2 });
+        let frame = encode(&method, &reply).unwrap();
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[1..5], (frame.len() as u32 - 5).to_be_bytes());
+        assert!(encode(&method, &serde_json::json!({ "unknown": 1 })).is_err());
+    }
+
+    #[test]
+    fn request_messages_are_decoded_to_json() {
+        let method = greeter("decode");
+        // HelloRequest { name: "ann" }, twice
+        let message = [0, 0, 0, 0, 5, 0x0a, 3, b'a', b'n', b'n'];
+        let body = Bytes::from([message, message].concat());
+        let name = serde_json::json!({ "name": "ann" });
+        assert_eq!(decode(&method, body).unwrap(), [name.clone(), name]);
+        let truncated = Bytes::from(message[..7].to_vec());
+        assert!(decode(&method, truncated).is_err());
+        let mut compressed = message;
+        compressed[0] = 1;
+        assert!(decode(&method, Bytes::from(compressed.to_vec())).is_err());
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let missing = load(&[PathBuf::from("missing.proto")], &[], None);
+        assert!(missing.is_err());
+        let descriptor_set = Path::new("missing.bin");
+        assert!(load(&[], &[], Some(descriptor_set)).is_err());
+    }
+
+    #[test]
+    fn unknown_methods_are_not_found() {
+        let method = greeter("unknown");
+        let pool = method.parent_pool();
+        assert!(find_method(pool, "/helloworld.Greeter/SayBye").is_none());
+        assert!(find_method(pool, "/helloworld.Other/SayHello").is_none());
+    }
+
+    #[test]
+    fn status_messages_are_percent_encoded() {
+        assert_eq!(encode_message("not 100% ok\n"), "not 100%25 ok%0A");
+        assert_eq!(encode_message("grüß"), "gr%C3%BC%C3%9F");
+    }
+}
@@ -1,25 +1,167 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use bytes::Bytes;
 use http::{
-    header::HeaderName, HeaderValue, Method, Uri,
+    header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE, LOCATION},
+    uri::PathAndQuery,
+    HeaderMap, HeaderValue, Method, Uri,
 };
-use hyper::{Request, Response};
-use http_body_util::{Full, BodyExt};
+use hyper::{body::Frame, Request, Response};
+use http_body_util::{combinators::UnsyncBoxBody, Full, BodyExt, StreamBody};
+use futures::StreamExt;
+use tokio_util::io::ReaderStream;
+use base64::Engine;
 use json_dotpath::DotPaths;
 
-use super::rule::{ Rule, error::ConfigurationError, then::Then} ;
+use super::configuration::{
+    BodyEncoding, ForwardUri, HeaderValues, ModifyRequest,
+};
+use super::graphql::{self, Operation};
+use super::rule::{ Rule, error::ConfigurationError, then::{self, Then}} ;
 use super::intermediary::{AsyncTryFrom, Intermediary};
+use super::{cookie, template, transform, xml};
+use crate::utility::log::{Loggable, LoggableType};
+use crate::PaintLogsCallbacks;
 
 use eyre::{Context, ContextCompat, Result};
 
+const NO_REQUEST_MODIFICATION: &Option<ModifyRequest> = &None;
+
 
 pub struct RuleAndIntermediaryHolder {
     pub rule: Rule,
     pub intermediary: Intermediary,
+    pub logging: Arc<PaintLogsCallbacks>,
 }
 
 impl RuleAndIntermediaryHolder {
+    // modifications that fail at runtime are logged and skipped
+    fn log_skipped(&self, modification: &str, error: impl std::fmt::Display) {
+        (self.logging.0)(&Loggable {
+            message_type: LoggableType::Plain,
+            message: format!(
+                "{modification} of Rule {} not applied: {error}",
+                self.rule.name
+            ),
+        });
+    }
+
+    fn upstream_uri(
+        &self,
+        forward_uri: &ForwardUri,
+    ) -> Result<Uri, ConfigurationError> {
+        let uri = match (forward_uri, &self.rule.state.balancer) {
+            (ForwardUri::Single(uri), _) => uri,
+            (ForwardUri::Balanced(_), Some(balancer)) => {
+                &balancer.select().ok_or(ConfigurationError::NoUriError)?.uri
+            }
+            (ForwardUri::Balanced(upstreams), None) => {
+                &upstreams.first().ok_or(ConfigurationError::NoUriError)?.uri
+            }
+        };
+        Ok(Uri::from_str(uri)?)
+    }
+
+    // static data of the operation or the result of executing it, errors are
+    // reported the graphql way
+    fn answer_graphql(
+        &self,
+        operation: &Operation,
+        resolvers: Option<&HashMap<String, serde_json::Value>>,
+        data: Option<&HashMap<String, serde_json::Value>>,
+    ) -> serde_json::Value {
+        let static_data = operation
+            .name
+            .as_ref()
+            .and_then(|name| data?.get(name));
+        if let Some(static_data) = static_data {
+            return serde_json::json!({ "data": static_data });
+        }
+
+        // the schema was parsed when the rule was loaded
+        let schema = self.rule.state.schema.as_deref();
+        let no_resolvers = HashMap::new();
+        match graphql::execute(
+            operation,
+            schema,
+            resolvers.unwrap_or(&no_resolvers),
+        ) {
+            Ok(data) => serde_json::json!({ "data": data }),
+            Err(e) => serde_json::json!({
+                "errors": [{ "message": e.to_string() }]
+            }),
+        }
+    }
+
+    // configured headers replace those of the same name, or add to them
+    // with `append`. Values are rendered when a request context is given
+    pub fn set_headers(
+        headers: &mut HeaderMap,
+        configured: Option<&HashMap<String, HeaderValues>>,
+        append: bool,
+        context: Option<&serde_json::Value>,
+    ) -> Result<(), ConfigurationError> {
+        for (key, values) in configured.into_iter().flatten() {
+            let name =
+                HeaderName::from_str(key).map_err(http::Error::from)?;
+            if !append {
+                headers.remove(&name);
+            }
+            for value in values.iter() {
+                let value = match context {
+                    Some(context) => template::render_str(value, context),
+                    None => value.clone(),
+                };
+                let value =
+                    HeaderValue::from_str(&value).map_err(http::Error::from)?;
+                headers.append(&name, value);
+            }
+        }
+        Ok(())
+    }
+
+    // string bodies of non json mocks are served verbatim or base64 decoded,
+    // the content length always matches what is sent
+    fn encode_mock_body(
+        intermediary: &mut Intermediary,
+        content_type: Option<&str>,
+        encoding: Option<&BodyEncoding>,
+    ) -> Result<(), ConfigurationError> {
+        // without a content type only structured bodies are json
+        let is_json = match content_type {
+            Some(content_type) => {
+                let mime = content_type.split(';').next().unwrap_or_default();
+                let mime = mime.trim().to_lowercase();
+                mime == "application/json" || mime.ends_with("+json")
+            }
+            None => !intermediary.body.is_string(),
+        };
+
+        if let serde_json::Value::String(body) = &intermediary.body {
+            let bytes = match encoding {
+                Some(BodyEncoding::Base64) => Some(Bytes::from(
+                    base64::engine::general_purpose::STANDARD
+                        .decode(body.trim())?,
+                )),
+                _ if !is_json => Some(Bytes::from(body.clone())),
+                _ => None,
+            };
+            if let Some(bytes) = bytes {
+                intermediary.body = serde_json::Value::Null;
+                intermediary.raw_body = Some(bytes);
+            }
+        }
+
+        let length = intermediary.body_bytes().len();
+        intermediary
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(length));
+        Ok(())
+    }
+
     fn apply_plugins_to_body(
         rule: &Rule,
         plugins: &crate::plugin_registry::ExternalFunctions,
@@ -108,50 +250,193 @@ impl TryFrom<&RuleAndIntermediaryHolder> for Request<Full<Bytes>> {
     fn try_from(
         holder: &RuleAndIntermediaryHolder,
     ) -> Result<Self, ConfigurationError> {
-        let mut builder = holder
-            .intermediary
-            .headers
-            .iter()
-            .fold(Request::builder(), |builder, (key, value)| {
-                builder.header(key, value)
-            });
-        match &holder.rule.then {
+        let (mut uri, modify_request) = match &holder.rule.then {
             Then::Fips {
                 forward_uri,
+                load_balancing: _,
+                modify_request,
                 modify_response: _,
-            } => {
-                builder = builder.uri(Uri::from_str(forward_uri)?);
-            }
+                fallback: _,
+            } => (holder.upstream_uri(forward_uri)?, modify_request),
             Then::Proxy {
                 forward_uri,
+                load_balancing: _,
+                modify_request,
                 modify_response: _,
-            } => {
-                builder = builder.uri(Uri::from_str(forward_uri)?);
-            }
+                fallback: _,
+            } => (holder.upstream_uri(forward_uri)?, modify_request),
             Then::Static { static_base_dir: _ } => {
                 return Err(ConfigurationError::NotForwarding);
             }
+            Then::Redirect {
+                location: _,
+                status: _,
+                preserve_query: _,
+                headers: _,
+            } => return Err(ConfigurationError::NotForwarding),
+            Then::WebSocket {
+                on_connect: _,
+                replies: _,
+                pushes: _,
+                close: _,
+                forward_uri: _,
+            } => return Err(ConfigurationError::NotForwarding),
+            Then::EventStream {
+                events: _,
+                repeat: _,
+                close: _,
+                headers: _,
+            } => return Err(ConfigurationError::NotForwarding),
+            Then::Grpc {
+                proto_files: _,
+                import_paths: _,
+                descriptor_set: _,
+                service: _,
+                method: _,
+                response: _,
+                status: _,
+                message: _,
+                headers: _,
+                trailers: _,
+            } => return Err(ConfigurationError::NotForwarding),
+            Then::GraphQL {
+                schema_file: _,
+                resolvers,
+                data,
+                forward_uri: Some(forward_uri),
+            } if !Operation::from_request(&holder.intermediary).is_some_and(
+                |operation| {
+                    operation.is_mocked(data.as_ref(), resolvers.as_ref())
+                },
+            ) =>
+            {
+                (Uri::from_str(forward_uri)?, NO_REQUEST_MODIFICATION)
+            }
+            Then::GraphQL {
+                schema_file: _,
+                resolvers: _,
+                data: _,
+                forward_uri: _,
+            } => return Err(ConfigurationError::NotForwarding),
+            Then::OAuth {
+                issuer: _,
+                clients: _,
+                claims: _,
+                expires_in: _,
+            } => return Err(ConfigurationError::NotForwarding),
             Then::Mock {
                 body: _,
+                body_file: _,
+                template: _,
+                content_type: _,
+                encoding: _,
                 status: _,
                 headers: _,
+                append_headers: _,
+                set_cookies: _,
+                delete_cookies: _,
             } => return Err(ConfigurationError::NotForwarding),
         };
-        builder = builder.method(
-            holder
-                .intermediary
-                .method
-                .as_ref()
-                .ok_or(ConfigurationError::NoMethodError)?
-                .clone(),
-        );
-        Ok(builder.body(Full::new(Bytes::from(holder.intermediary.body.to_string())))?)
+        let mut method = holder
+            .intermediary
+            .method
+            .as_ref()
+            .ok_or(ConfigurationError::NoMethodError)?
+            .clone();
+        let mut intermediary = holder.intermediary.clone();
+
+        if let Some(modify) = modify_request {
+            if let Some(keep_headers) = &modify.keep_headers {
+                let keep = keep_headers
+                    .iter()
+                    .map(|h| HeaderName::from_str(h))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(http::Error::from)?;
+                let dropped = intermediary
+                    .headers
+                    .keys()
+                    .filter(|key| !keep.contains(key))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for h in dropped {
+                    intermediary.headers.remove(h);
+                }
+            }
+
+            if let Some(delete_headers) = &modify.delete_headers {
+                for h in delete_headers {
+                    intermediary.headers.remove(h);
+                }
+            }
+
+            RuleAndIntermediaryHolder::set_headers(
+                &mut intermediary.headers,
+                modify.add_headers.as_ref(),
+                false,
+                None,
+            )?;
+
+            if let Some(new_method) = &modify.method {
+                method = Method::from_str(&new_method.to_uppercase())
+                    .map_err(http::Error::from)?;
+            }
+
+            if let Some(set_query) = &modify.set_query {
+                uri = with_query(uri, set_query)?;
+            }
+
+            if let Some(manipulator) = &modify.body {
+                for m in manipulator {
+                    intermediary.body.dot_set(&m.at, &m.with)?;
+                }
+            }
+        }
+
+        // the body is serialized anew, let hyper compute its length
+        intermediary.headers.remove(CONTENT_LENGTH);
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Full::new(intermediary.body_bytes()))?;
+        *request.headers_mut() = intermediary.headers;
+        Ok(request)
     }
 }
 
+// sets the given query parameters on the uri, replacing existing ones
+fn with_query(
+    uri: Uri,
+    set_query: &HashMap<String, String>,
+) -> Result<Uri, ConfigurationError> {
+    let mut parts = uri.into_parts();
+    let (path, query) = parts
+        .path_and_query
+        .as_ref()
+        .map_or(("/", ""), |pq| (pq.path(), pq.query().unwrap_or("")));
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _)| !set_query.contains_key(key.as_ref()))
+        .for_each(|(key, value)| {
+            serializer.append_pair(&key, &value);
+        });
+    for (key, value) in set_query {
+        serializer.append_pair(key, value);
+    }
+
+    parts.path_and_query = Some(
+        PathAndQuery::from_str(&format!("{path}?{}", serializer.finish()))?,
+    );
+    Ok(Uri::from_parts(parts).map_err(http::Error::from)?)
+}
+
 // convert to response
-impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
-    type Output = Response<Full<Bytes>>;
+// file bodies are streamed, everything else is sent at once
+impl AsyncTryFrom<RuleAndIntermediaryHolder>
+    for Response<UnsyncBoxBody<Bytes, Infallible>>
+{
+    type Output = Response<UnsyncBoxBody<Bytes, Infallible>>;
     async fn async_try_from(
         mut holder: RuleAndIntermediaryHolder,
     ) -> Result<Self> {
@@ -165,13 +450,17 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
 
         builder.headers_mut().wrap_err("hyper object has no headers")?.remove("content-length");
         holder.intermediary.headers.remove(HeaderName::from_static("content-length"));
+        let mut streamed_file = None;
 
         match &holder.rule.then {
             //plugins/transformation/status/headers
             //TODO plugins
             Then::Fips {
                 forward_uri: _,
+                load_balancing: _,
+                modify_request: _,
                 modify_response,
+                fallback: _,
             } => {
                 if let Some(modify) = modify_response {
                     if let Some(status) = &modify.status {
@@ -179,6 +468,33 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                             .status(hyper::StatusCode::from_str(status)?);
                     }
 
+                    if let (Some(manipulations), Some(patterns)) =
+                        (&modify.text, &holder.rule.state.text)
+                    {
+                        let text = transform::text(
+                            manipulations,
+                            patterns,
+                            holder.intermediary.body_text(),
+                        );
+                        holder.intermediary.set_body_bytes(Bytes::from(text));
+                    }
+
+                    // bodies that are no xml are passed on unchanged
+                    if let (Some(manipulations), Some(xpaths)) =
+                        (&modify.xml, &holder.rule.state.xpaths)
+                    {
+                        match xml::modify(
+                            &holder.intermediary.body_text(),
+                            manipulations,
+                            xpaths,
+                        ) {
+                            Ok(xml) => holder
+                                .intermediary
+                                .set_body_bytes(Bytes::from(xml)),
+                            Err(e) => holder.log_skipped("xml modification", e),
+                        }
+                    }
+
                     //morph body
                     if let Some(manipulator) = &modify.body {
                         manipulator.iter().for_each(|m| {
@@ -188,6 +504,32 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                         });
                     }
 
+                    if let Some(merge_patch) = &modify.merge_patch {
+                        json_patch::merge(
+                            &mut holder.intermediary.body,
+                            merge_patch,
+                        );
+                    }
+
+                    // a failing operation (e.g. `test`) discards the patch
+                    if let Some(patch) = &modify.patch {
+                        if let Err(e) = json_patch::patch(
+                            &mut holder.intermediary.body,
+                            patch,
+                        ) {
+                            holder.log_skipped("JSON patch", e);
+                        }
+                    }
+
+                    // compiled from `transform` when the rule was loaded, the
+                    // body stays as is if the filter fails
+                    if let Some(jq) = &holder.rule.state.jq {
+                        match jq.run(holder.intermediary.body.clone()) {
+                            Ok(body) => holder.intermediary.body = body,
+                            Err(e) => holder.log_skipped("jq transform", e),
+                        }
+                    }
+
                     if let Some(headers) = &modify.delete_headers {
                         for h in headers {
                             if holder.intermediary.headers.contains_key(h) {
@@ -196,22 +538,44 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                         }
                     }
 
-                    if let Some(add_headers) = &modify.set_headers {
-                        for (key, value) in add_headers.iter() {
-                            if holder.intermediary.headers.contains_key(key) {
-                                holder.intermediary.headers.remove(key);
-                            }
-                            builder = builder.header(key, value);
-                        }
-                    }
+                    RuleAndIntermediaryHolder::set_headers(
+                        &mut holder.intermediary.headers,
+                        modify.set_headers.as_ref(),
+                        false,
+                        None,
+                    )?;
+                    RuleAndIntermediaryHolder::set_headers(
+                        &mut holder.intermediary.headers,
+                        modify.append_headers.as_ref(),
+                        true,
+                        None,
+                    )?;
+
+                    cookie::apply(
+                        &mut holder.intermediary.headers,
+                        modify.set_cookies.as_ref(),
+                        modify.delete_cookies.as_ref(),
+                        None,
+                    )?;
                 }
             }
             //plugins/headers/status
             Then::Mock {
                 body,
+                body_file,
+                template,
+                content_type,
+                encoding,
                 status,
                 headers,
+                append_headers,
+                set_cookies,
+                delete_cookies,
             } => {
+                // the intermediary still holds the request at this point
+                let context =
+                    template::context(&holder.intermediary, &holder.rule);
+
                 if let Some(status) = status {
                     builder =
                         builder.status(hyper::StatusCode::from_str(status)?);
@@ -219,35 +583,122 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                     builder = builder.status(hyper::StatusCode::OK)
                 }
                 if let Some(body) = body {
+                    let mut body = body.clone();
+                    template::render(&mut body, &context);
                     holder.intermediary.body = body.clone();
-                }
-                if let Some(headers) = headers {
-                    for (key, value) in headers.iter() {
+                    holder.intermediary.raw_body = None;
+
+                    // xml documents are served as they are
+                    if let Some(content_type) =
+                        body.as_str().and_then(xml::content_type)
+                    {
+                        holder.intermediary.set_body_bytes(Bytes::from(
+                            body.as_str().unwrap_or_default().to_string(),
+                        ));
                         holder.intermediary.headers.insert(
-                            HeaderName::from_str(key)?,
-                            HeaderValue::from_str(value)?,
+                            CONTENT_TYPE,
+                            HeaderValue::from_static(content_type),
                         );
                     }
                 }
+                if let Some(body_file) = body_file {
+                    let path = holder.rule.relative_path(body_file);
+                    let read_error =
+                        || format!("could not read {}", path.display());
+                    // only templates are read into memory, other files are
+                    // streamed byte for byte
+                    let bytes = if template.unwrap_or(false) {
+                        let bytes = tokio::fs::read(&path)
+                            .await
+                            .wrap_err_with(read_error)?;
+                        let text = String::from_utf8_lossy(&bytes);
+                        Bytes::from(template::render_str(&text, &context))
+                    } else {
+                        let file = tokio::fs::File::open(&path)
+                            .await
+                            .wrap_err_with(read_error)?;
+                        let length = file.metadata().await?.len();
+                        streamed_file = Some((file, length));
+                        Bytes::new()
+                    };
+
+                    holder.intermediary.body = serde_json::Value::Null;
+                    holder.intermediary.raw_body = Some(bytes);
+                    let content_type =
+                        mime_guess::from_path(&path).first_or_octet_stream();
+                    holder.intermediary.headers.insert(
+                        CONTENT_TYPE,
+                        HeaderValue::from_str(content_type.as_ref())?,
+                    );
+                }
+                if let Some(content_type) = content_type {
+                    holder.intermediary.headers.insert(
+                        CONTENT_TYPE,
+                        HeaderValue::from_str(content_type)?,
+                    );
+                } else if body.is_some()
+                    && body_file.is_none()
+                    && holder.intermediary.raw_body.is_none()
+                {
+                    let served = &holder.intermediary.body;
+                    let content_type = match (encoding, served) {
+                        (Some(BodyEncoding::Base64), _) => {
+                            "application/octet-stream"
+                        }
+                        (_, serde_json::Value::String(_)) => {
+                            "text/plain; charset=utf-8"
+                        }
+                        _ => "application/json",
+                    };
+                    holder.intermediary.headers.insert(
+                        CONTENT_TYPE,
+                        HeaderValue::from_static(content_type),
+                    );
+                }
+                RuleAndIntermediaryHolder::set_headers(
+                    &mut holder.intermediary.headers,
+                    headers.as_ref(),
+                    false,
+                    Some(&context),
+                )?;
+                RuleAndIntermediaryHolder::set_headers(
+                    &mut holder.intermediary.headers,
+                    append_headers.as_ref(),
+                    true,
+                    Some(&context),
+                )?;
+                cookie::apply(
+                    &mut holder.intermediary.headers,
+                    set_cookies.as_ref(),
+                    delete_cookies.as_ref(),
+                    Some(&context),
+                )?;
             }
             //headers
             Then::Proxy {
                 forward_uri: _,
+                load_balancing: _,
+                modify_request: _,
                 modify_response,
+                fallback: _,
             } => {
                 if let Some(modify_response) = modify_response {
                     if let Some(status) = &modify_response.status {
                         builder = builder
                             .status(hyper::StatusCode::from_str(status)?);
                     }
-                    if let Some(add_headers) = &modify_response.add_headers {
-                        for (key, value) in add_headers.iter() {
-                            holder.intermediary.headers.insert(
-                                HeaderName::from_str(key)?,
-                                HeaderValue::from_str(value)?,
-                            );
-                        }
-                    }
+                    RuleAndIntermediaryHolder::set_headers(
+                        &mut holder.intermediary.headers,
+                        modify_response.add_headers.as_ref(),
+                        false,
+                        None,
+                    )?;
+                    RuleAndIntermediaryHolder::set_headers(
+                        &mut holder.intermediary.headers,
+                        modify_response.append_headers.as_ref(),
+                        true,
+                        None,
+                    )?;
                     if let Some(delete_headers) =
                         &modify_response.delete_headers
                     {
@@ -257,8 +708,121 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                             }
                         }
                     }
+                    cookie::apply(
+                        &mut holder.intermediary.headers,
+                        modify_response.set_cookies.as_ref(),
+                        modify_response.delete_cookies.as_ref(),
+                        None,
+                    )?;
+                }
+            }
+            Then::Redirect {
+                location,
+                status,
+                preserve_query,
+                headers,
+            } => {
+                builder = builder.status(then::redirect_status(
+                    status.as_deref(),
+                )?);
+
+                let context =
+                    template::context(&holder.intermediary, &holder.rule);
+                let mut location = template::render_str(location, &context);
+                let query = holder.intermediary.uri.as_ref().and_then(|uri| {
+                    uri.query().filter(|query| !query.is_empty())
+                });
+                if let (Some(true), Some(query)) = (preserve_query, query) {
+                    let separator =
+                        if location.contains('?') { '&' } else { '?' };
+                    location = format!("{location}{separator}{query}");
                 }
+
+                // nothing of the request is sent back
+                holder.intermediary.headers.clear();
+                holder.intermediary.body = serde_json::Value::Null;
+                holder.intermediary.raw_body = Some(Bytes::new());
+                holder
+                    .intermediary
+                    .headers
+                    .insert(LOCATION, HeaderValue::from_str(&location)?);
+                RuleAndIntermediaryHolder::set_headers(
+                    &mut holder.intermediary.headers,
+                    headers.as_ref(),
+                    false,
+                    None,
+                )?;
             }
+            Then::GraphQL {
+                schema_file: _,
+                resolvers,
+                data,
+                forward_uri: _,
+            } => {
+                // answers of the upstream pass unchanged, only requests
+                // carry a method
+                if holder.intermediary.method.is_some() {
+                    let mut answer =
+                        match Operation::from_request(&holder.intermediary) {
+                            Some(operation) => holder.answer_graphql(
+                                &operation,
+                                resolvers.as_ref(),
+                                data.as_ref(),
+                            ),
+                            None => {
+                                builder = builder
+                                    .status(hyper::StatusCode::BAD_REQUEST);
+                                serde_json::json!({ "errors": [{
+                                    "message": "no graphql operation found"
+                                }]})
+                            }
+                        };
+                    let context =
+                        template::context(&holder.intermediary, &holder.rule);
+                    template::render(&mut answer, &context);
+
+                    holder.intermediary.headers.clear();
+                    holder.intermediary.headers.insert(
+                        CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    );
+                    holder.intermediary.body = answer;
+                    holder.intermediary.raw_body = None;
+                }
+            }
+            // upgrades, event streams, grpc calls and the oauth provider are
+            // answered by the router
+            Then::WebSocket {
+                on_connect: _,
+                replies: _,
+                pushes: _,
+                close: _,
+                forward_uri: _,
+            }
+            | Then::EventStream {
+                events: _,
+                repeat: _,
+                close: _,
+                headers: _,
+            }
+            | Then::Grpc {
+                proto_files: _,
+                import_paths: _,
+                descriptor_set: _,
+                service: _,
+                method: _,
+                response: _,
+                status: _,
+                message: _,
+                headers: _,
+                trailers: _,
+            }
+            | Then::OAuth {
+                issuer: _,
+                clients: _,
+                claims: _,
+                expires_in: _,
+            } => return Err(ConfigurationError::NotForwarding.into()),
             //nothing
             Then::Static { static_base_dir } => {
                 if let Some(path) = static_base_dir {
@@ -272,10 +836,10 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
                         .serve(fake_request)
                         .await?;
                     
-                    // Convert the hyper_staticfile response to our Response<Full<Bytes>> type
+                    // Convert the hyper_staticfile response to our response type
                     let (parts, body) = resp.into_parts();
                     let body_bytes = body.collect().await?.to_bytes();
-                    let converted_resp = Response::from_parts(parts, Full::new(body_bytes));
+                    let converted_resp = Response::from_parts(parts, Full::new(body_bytes).boxed_unsync());
                     return Ok(converted_resp);
                 }
             }
@@ -311,7 +875,37 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
             }
         }
 
-        let resp_body = Full::new(Bytes::from(holder.intermediary.body.to_string()));
+        if let Then::Mock {
+            content_type,
+            encoding,
+            ..
+        } = &holder.rule.then
+        {
+            RuleAndIntermediaryHolder::encode_mock_body(
+                &mut holder.intermediary,
+                content_type.as_deref(),
+                encoding.as_ref(),
+            )?;
+        }
+
+        let resp_body = match streamed_file {
+            Some((file, length)) => {
+                holder
+                    .intermediary
+                    .headers
+                    .insert(CONTENT_LENGTH, HeaderValue::from(length));
+                let rule = holder.rule.name.clone();
+                let frames = ReaderStream::new(file).filter_map(move |chunk| {
+                    // the stream ends after a read error
+                    if let Err(e) = &chunk {
+                        log::error!("could not stream file of {rule}: {e}");
+                    }
+                    futures::future::ready(chunk.ok().map(Frame::data).map(Ok))
+                });
+                StreamBody::new(frames).boxed_unsync()
+            }
+            None => Full::new(holder.intermediary.body_bytes()).boxed_unsync(),
+        };
 
         //flush the header map
         builder
@@ -328,3 +922,388 @@ impl AsyncTryFrom<RuleAndIntermediaryHolder> for Response<Full<Bytes>> {
         Ok(builder.body(resp_body)?)
     }
 }
+
+#[cfg(test)]
+impl RuleAndIntermediaryHolder {
+    // a holder whose logs go nowhere
+    pub fn new(rule: Rule, intermediary: Intermediary) -> Self {
+        RuleAndIntermediaryHolder {
+            rule,
+            intermediary,
+            logging: Arc::new(PaintLogsCallbacks(Box::new(|_| {}))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARD: &str = r#"
+name: forward
+when:
+  matchesUris:
+    - uri: ^/api
+then:
+  functionAs: Fips
+  forwardUri: http://upstream:8080/users?keep=yes
+"#;
+
+    fn forwarded(
+        modify_request: &str,
+        request: Intermediary,
+    ) -> Result<Request<Full<Bytes>>, ConfigurationError> {
+        let yaml = format!("{FORWARD}  modifyRequest:\n{modify_request}");
+        let rule = Rule::from_yaml(&yaml).unwrap();
+        Request::try_from(&RuleAndIntermediaryHolder::new(rule, request))
+    }
+
+    async fn respond(
+        rule: Rule,
+        intermediary: Intermediary,
+    ) -> Response<UnsyncBoxBody<Bytes, Infallible>> {
+        let holder = RuleAndIntermediaryHolder::new(rule, intermediary);
+        Response::async_try_from(holder).await.unwrap()
+    }
+
+    // the answer of a forwarding rule to an upstream response with this body
+    async fn modified(modify_response: &str, body: &[u8]) -> Bytes {
+        let yaml = format!("{FORWARD}  modifyResponse:\n{modify_response}");
+        let rule = Rule::from_yaml(&yaml).unwrap();
+        let upstream = Intermediary::request(Method::GET, "/api", body);
+        let resp = respond(rule, upstream).await;
+        resp.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[test]
+    fn request_goes_to_the_forward_uri() {
+        let request = Intermediary::request(Method::GET, "/api", b"");
+        let rule = Rule::from_yaml(FORWARD).unwrap();
+        let forwarded =
+            Request::try_from(&RuleAndIntermediaryHolder::new(rule, request))
+                .unwrap();
+        assert_eq!(forwarded.uri(), "http://upstream:8080/users?keep=yes");
+    }
+
+    #[test]
+    fn mocks_are_not_forwarded() {
+        let rule = Rule::from_yaml(
+            "name: mock\nwhen:\n  matchesUris:\n    - uri: ^/\nthen:\n  \
+             functionAs: Mock\n  body: hello\n",
+        )
+        .unwrap();
+        let request = Intermediary::request(Method::GET, "/", b"");
+        assert!(matches!(
+            Request::try_from(&RuleAndIntermediaryHolder::new(rule, request)),
+            Err(ConfigurationError::NotForwarding)
+        ));
+    }
+
+    #[test]
+    fn modify_request_changes_headers_method_and_query() {
+        let mut request = Intermediary::request(Method::GET, "/api", b"");
+        request
+            .headers
+            .insert("x-drop", HeaderValue::from_static("1"));
+        request
+            .headers
+            .insert("x-keep", HeaderValue::from_static("1"));
+        let forwarded = forwarded(
+            r#"
+    deleteHeaders: [x-drop]
+    setHeaders:
+      x-added: [one, two]
+    method: post
+    setQuery:
+      page: "2"
+"#,
+            request,
+        )
+        .unwrap();
+        assert_eq!(forwarded.method(), Method::POST);
+        assert_eq!(forwarded.uri().query(), Some("keep=yes&page=2"));
+        assert!(forwarded.headers().get("x-drop").is_none());
+        assert!(forwarded.headers().get("x-keep").is_some());
+        let added: Vec<_> =
+            forwarded.headers().get_all("x-added").iter().collect();
+        assert_eq!(added, ["one", "two"]);
+    }
+
+    #[test]
+    fn keep_headers_drops_all_others() {
+        let mut request = Intermediary::request(Method::GET, "/api", b"");
+        request
+            .headers
+            .insert("x-one", HeaderValue::from_static("1"));
+        request
+            .headers
+            .insert("x-two", HeaderValue::from_static("2"));
+        let forwarded =
+            forwarded("    keepHeaders: [x-one]\n", request).unwrap();
+        assert_eq!(forwarded.headers().len(), 1);
+        assert!(forwarded.headers().contains_key("x-one"));
+    }
+
+    #[tokio::test]
+    async fn modify_request_sets_body_fields() {
+        let request =
+            Intermediary::request(Method::POST, "/api", br#"{"a":1}"#);
+        let forwarded =
+            forwarded("    body:\n      - at: b.c\n        with: 2\n", request)
+                .unwrap();
+        let body = forwarded.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({"a": 1, "b": {"c": 2}}));
+    }
+
+    #[test]
+    fn invalid_modifications_are_errors() {
+        let request = || Intermediary::request(Method::GET, "/api", b"");
+        assert!(forwarded(
+            "    setHeaders:\n      \"bad header\": x\n",
+            request()
+        )
+        .is_err());
+        assert!(forwarded("    method: \"GE T\"\n", request()).is_err());
+    }
+
+    #[tokio::test]
+    async fn failing_jq_keeps_the_upstream_body() {
+        let body = modified("    transform: .foo\n", b"[1]").await;
+        assert_eq!(body, "[1]");
+    }
+
+    #[tokio::test]
+    async fn jq_transforms_the_upstream_body() {
+        let body = modified("    transform: .a\n", br#"{"a":2}"#).await;
+        assert_eq!(body, "2");
+    }
+
+    #[tokio::test]
+    async fn failing_xml_modifications_keep_the_upstream_body() {
+        let xml = "    xml:\n      - at: //id\n        set: '8'\n";
+        let body = modified(xml, br#"{"id":7}"#).await;
+        assert_eq!(body, r#"{"id":7}"#);
+    }
+
+    #[tokio::test]
+    async fn body_files_are_read_next_to_the_rule() {
+        let dir = std::env::temp_dir()
+            .join(format!("fips-body-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("body.html"), "<p>hi</p>").unwrap();
+        let mut rule = Rule::from_yaml(
+            "name: file\nwhen:\n  matchesUris:\n    - uri: ^/\nthen:\n  \
+             functionAs: Mock\n  bodyFile: body.html\n",
+        )
+        .unwrap();
+        rule.path = dir.join("rules.yaml").to_string_lossy().into_owned();
+        let request = Intermediary::request(Method::GET, "/", b"");
+        let resp = respond(rule, request).await;
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/html");
+        assert_eq!(resp.headers()[CONTENT_LENGTH], "9");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(body, "<p>hi</p>");
+    }
+
+    fn redirect(then: &str) -> Result<Rule, ConfigurationError> {
+        Rule::from_yaml(&format!(
+            "name: redirect\nwhen:\n  matchesUris:\n    \
+             - uri: ^/login/(?P<tenant>\\w+)\nthen:\n  \
+             functionAs: Redirect\n{then}"
+        ))
+    }
+
+    #[tokio::test]
+    async fn redirects_render_the_location() {
+        let rule = redirect(
+            "  location: https://sso/{{request.captures.tenant}}?x=1\n  \
+             preserveQuery: true\n  status: \"308\"\n",
+        )
+        .unwrap();
+        let request =
+            Intermediary::request(Method::GET, "/login/acme?y=2", b"");
+        let resp = respond(rule, request).await;
+        assert_eq!(resp.status(), 308);
+        assert_eq!(resp.headers()[LOCATION], "https://sso/acme?x=1&y=2");
+    }
+
+    #[tokio::test]
+    async fn redirects_default_to_found() {
+        let rule = redirect("  location: /home\n").unwrap();
+        let request =
+            Intermediary::request(Method::GET, "/login/acme?y=2", b"");
+        let resp = respond(rule, request).await;
+        assert_eq!(resp.status(), 302);
+        assert_eq!(resp.headers()[LOCATION], "/home");
+    }
+
+    #[test]
+    fn other_redirect_statuses_are_rejected() {
+        assert!(redirect("  location: /\n  status: \"200\"\n").is_err());
+        assert!(redirect("  location: /\n  status: moved\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn merge_patch_and_patch_change_the_upstream_body() {
+        let modify = "    mergePatch:\n      a: null\n      b: { c: 2 }\n    \
+            patch:\n      - { op: add, path: /items/-, value: 3 }\n";
+        let upstream = br#"{"a":1,"b":{"d":1},"items":[1]}"#;
+        let body = modified(modify, upstream).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "b": { "c": 2, "d": 1 }, "items": [1, 3] })
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_patches_are_discarded() {
+        let modify =
+            "    patch:\n      - { op: replace, path: /a, value: 2 }\n\
+            \x20     - { op: test, path: /b, value: 1 }\n";
+        let body = modified(modify, br#"{"a":1}"#).await;
+        assert_eq!(body, r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn patch_paths_are_checked_when_the_rule_is_loaded() {
+        let yaml = format!(
+            "{FORWARD}  modifyResponse:\n    patch:\n      \
+                - {{ op: remove, path: a }}\n"
+        );
+        assert!(Rule::from_yaml(&yaml).is_err());
+    }
+
+    // the answer of a mock rule to a GET of /api?id=7
+    async fn mocked(then: &str) -> (hyper::StatusCode, String, Bytes) {
+        let rule = Rule::from_yaml(&format!(
+            "name: mock\nwhen:\n  matchesUris:\n    - uri: ^/api\nthen:\n  \
+             functionAs: Mock\n{then}"
+        ))
+        .unwrap();
+        let request = Intermediary::request(Method::GET, "/api?id=7", b"");
+        let resp = respond(rule, request).await;
+        let status = resp.status();
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        (status, content_type, body)
+    }
+
+    #[tokio::test]
+    async fn mocks_serve_json_with_their_status() {
+        let (status, content_type, body) = mocked(
+            "  status: \"201\"\n  body: { id: '{{request.query.id}}' }\n",
+        )
+        .await;
+        assert_eq!(status, hyper::StatusCode::CREATED);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, r#"{"id":"7"}"#);
+    }
+
+    #[tokio::test]
+    async fn string_mocks_are_served_as_text() {
+        let (status, content_type, body) =
+            mocked("  body: id {{request.query.id}}\n").await;
+        assert_eq!(status, hyper::StatusCode::OK);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(body, "id 7");
+    }
+
+    #[tokio::test]
+    async fn xml_mocks_get_an_xml_content_type() {
+        let (_, content_type, body) =
+            mocked("  body: '<?xml version=\"1.0\"?><a/>'\n").await;
+        assert!(content_type.contains("xml"));
+        assert_eq!(body, "<?xml version=\"1.0\"?><a/>");
+    }
+
+    #[tokio::test]
+    async fn base64_mocks_are_decoded() {
+        let (_, content_type, body) =
+            mocked("  body: aGk=\n  encoding: Base64\n").await;
+        assert_eq!(content_type, "application/octet-stream");
+        assert_eq!(body, "hi");
+    }
+
+    #[tokio::test]
+    async fn configured_content_types_are_kept() {
+        let (_, content_type, body) =
+            mocked("  body: a,b\n  contentType: text/csv\n").await;
+        assert_eq!(content_type, "text/csv");
+        assert_eq!(body, "a,b");
+    }
+
+    fn header_values(headers: &HeaderMap, name: &str) -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn header_lists_send_the_header_once_per_value() {
+        let configured: HashMap<String, HeaderValues> = serde_yaml::from_str(
+            "link: [</a>; rel=next, </b>; rel=prev]\n\
+             x-id: '{{request.query.id}}'",
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("link", HeaderValue::from_static("</old>"));
+        let context =
+            serde_json::json!({ "request": { "query": { "id": 7 } } });
+        RuleAndIntermediaryHolder::set_headers(
+            &mut headers,
+            Some(&configured),
+            false,
+            Some(&context),
+        )
+        .unwrap();
+        assert_eq!(
+            header_values(&headers, "link"),
+            ["</a>; rel=next", "</b>; rel=prev"]
+        );
+        assert_eq!(header_values(&headers, "x-id"), ["7"]);
+    }
+
+    #[test]
+    fn appended_headers_keep_the_existing_values() {
+        let configured: HashMap<String, HeaderValues> =
+            serde_yaml::from_str("vary: [origin, accept]").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("vary", HeaderValue::from_static("cookie"));
+        RuleAndIntermediaryHolder::set_headers(
+            &mut headers,
+            Some(&configured),
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            header_values(&headers, "vary"),
+            ["cookie", "origin", "accept"]
+        );
+    }
+
+    #[tokio::test]
+    async fn mock_headers_can_repeat() {
+        let rule = Rule::from_yaml(
+            "name: mock\nwhen:\n  matchesUris:\n    - uri: ^/\nthen:\n  \
+             functionAs: Mock\n  headers:\n    x-a: [one, two]\n  \
+             appendHeaders:\n    x-a: three\n",
+        )
+        .unwrap();
+        let request = Intermediary::request(Method::GET, "/", b"");
+        let resp = respond(rule, request).await;
+        assert_eq!(
+            header_values(resp.headers(), "x-a"),
+            ["one", "two", "three"]
+        );
+    }
+}
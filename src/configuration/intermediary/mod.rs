@@ -1,10 +1,12 @@
 use bytes::Bytes;
 use eyre::Result;
-use http::{HeaderMap, Method, StatusCode, Uri};
+use http::{header::HOST, HeaderMap, Method, StatusCode, Uri};
 use hyper::{Request, Response};
 use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
+use std::net::SocketAddr;
 
+use super::form::Form;
 use super::rule::error::ConfigurationError;
 
 #[derive(Debug, Clone)]
@@ -12,8 +14,71 @@ pub struct Intermediary {
     pub status: StatusCode,
     pub headers: HeaderMap,
     pub body: serde_json::Value,
+    /// The payload as received if it is not json, only used while `body`
+    /// is null
+    pub raw_body: Option<Bytes>,
+    /// Fields and files of a form request, the body itself is kept as is
+    pub form: Option<Form>,
     pub method: Option<Method>,
     pub uri: Option<Uri>,
+    /// Address a request was sent from
+    pub client: Option<SocketAddr>,
+}
+
+impl Intermediary {
+    pub fn body_bytes(&self) -> Bytes {
+        match (&self.raw_body, &self.body) {
+            (Some(raw), serde_json::Value::Null) => raw.clone(),
+            _ => Bytes::from(self.body.to_string()),
+        }
+    }
+
+    pub fn set_body_bytes(&mut self, bytes: Bytes) {
+        match serde_json::from_slice(&bytes) {
+            Ok(json) => {
+                self.body = json;
+                self.raw_body = None;
+            }
+            Err(_) => {
+                self.body = serde_json::Value::Null;
+                self.raw_body = Some(bytes);
+            }
+        }
+    }
+
+    /// Host the request was sent to without its port, taken from the uri or
+    /// the Host header
+    pub fn host(&self) -> Option<String> {
+        let authority = match self.uri.as_ref().and_then(|uri| uri.authority())
+        {
+            Some(authority) => authority.clone(),
+            None => self.headers.get(HOST)?.to_str().ok()?.parse().ok()?,
+        };
+        Some(authority.host().to_lowercase())
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body_bytes()).into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Intermediary {
+    // a request as it arrives at the rules
+    pub fn request(method: Method, uri: &str, body: &[u8]) -> Intermediary {
+        let mut request = Intermediary {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: serde_json::Value::Null,
+            raw_body: None,
+            form: None,
+            method: Some(method),
+            uri: Some(uri.parse().unwrap()),
+            client: None,
+        };
+        request.set_body_bytes(Bytes::copy_from_slice(body));
+        request
+    }
 }
 
 pub trait AsyncTryFrom<T> {
@@ -33,15 +98,18 @@ impl AsyncTryFrom<hyper::Response<Incoming>> for Intermediary {
 
         let body = response.into_body();
         let body_bytes = body.collect().await?.to_bytes();
-        let resp_json: serde_json::Value =
-            serde_json::from_slice(&body_bytes).unwrap_or_default();
-        Ok(Intermediary {
+        let mut intermediary = Intermediary {
             status,
             headers,
-            body: resp_json,
+            body: serde_json::Value::Null,
+            raw_body: None,
+            form: None,
             method: None,
             uri: None,
-        })
+            client: None,
+        };
+        intermediary.set_body_bytes(body_bytes);
+        Ok(intermediary)
     }
 }
 
@@ -53,17 +121,22 @@ impl AsyncTryFrom<hyper::Request<Incoming>> for Intermediary {
         let method = request.method().clone();
         let uri = request.uri().clone();
         let headers = request.headers().clone();
+        let client = request.extensions().get::<SocketAddr>().copied();
         let body = request.into_body();
         let body_bytes = body.collect().await?.to_bytes();
-        let req_json: serde_json::Value =
-            serde_json::from_slice(&body_bytes).unwrap_or_default();
-        Ok(Intermediary {
+        let mut intermediary = Intermediary {
             status: StatusCode::OK,
-            headers,
-            body: req_json,
+            headers: headers.clone(),
+            body: serde_json::Value::Null,
+            raw_body: None,
+            form: None,
             method: Some(method),
             uri: Some(uri),
-        })
+            client,
+        };
+        intermediary.form = Form::parse(&headers, &body_bytes).await;
+        intermediary.set_body_bytes(body_bytes);
+        Ok(intermediary)
     }
 }
 
@@ -74,8 +147,7 @@ impl From<Intermediary> for hyper::Response<Full<Bytes>> {
         for (key, value) in intermediary.headers.iter() {
             builder = builder.header(key, value);
         }
-        let body = serde_json::to_string(&intermediary.body).unwrap();
-        builder.body(Full::new(Bytes::from(body))).unwrap()
+        builder.body(Full::new(intermediary.body_bytes())).unwrap()
     }
 }
 
@@ -84,6 +156,7 @@ impl TryFrom<Intermediary> for hyper::Request<Full<Bytes>> {
     fn try_from(
         intermediary: Intermediary,
     ) -> Result<Self, ConfigurationError> {
+        let body = intermediary.body_bytes();
         let mut builder = Request::builder();
         if let Some(method) = intermediary.method {
             builder = builder.method(method);
@@ -98,6 +171,6 @@ impl TryFrom<Intermediary> for hyper::Request<Full<Bytes>> {
         for (key, value) in intermediary.headers.iter() {
             builder = builder.header(key, value);
         }
-        Ok(builder.body(Full::new(Bytes::from(intermediary.body.to_string())))?)
+        Ok(builder.body(Full::new(body))?)
     }
 }
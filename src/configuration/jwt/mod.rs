@@ -0,0 +1,263 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::{header::AUTHORIZATION, HeaderMap};
+use json_dotpath::DotPaths;
+use jsonwebtoken::{errors::ErrorKind, jwk::JwkSet, DecodingKey, Validation};
+use regex::Regex;
+use serde_json::Value;
+
+use super::configuration::JwtMatch;
+use super::rule::{error::ConfigurationError, Rule};
+
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// claims of the bearer token as sent, for templates
+pub fn unverified_claims(headers: &HeaderMap) -> Option<Value> {
+    let payload = bearer(headers)?.split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+enum Keys {
+    // keys of a jwks file by their `kid`
+    Jwks(Vec<(Option<String>, DecodingKey)>),
+    Key(DecodingKey),
+    // only the expiry is checked
+    None,
+}
+
+// the keys and claim patterns of a `matchesJwt` block, read once when the
+// rule is loaded
+pub struct Verifier {
+    keys: Keys,
+    allow_expired: bool,
+    claims: Vec<(String, Regex)>,
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Verifier")
+            .field("allow_expired", &self.allow_expired)
+            .field("claims", &self.claims)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Verifier {
+    pub fn new(
+        jwt_match: &JwtMatch,
+        rule: &Rule,
+    ) -> Result<Verifier, ConfigurationError> {
+        let read = |file: &str| {
+            let path = rule.relative_path(file);
+            std::fs::read(&path).map_err(|e| {
+                ConfigurationError::KeyFile(format!("{}: {e}", path.display()))
+            })
+        };
+        let keys = if let Some(jwks_file) = &jwt_match.jwks_file {
+            let jwks: JwkSet = serde_json::from_slice(&read(jwks_file)?)
+                .map_err(|e| {
+                    ConfigurationError::KeyFile(format!("{jwks_file}: {e}"))
+                })?;
+            let keys = jwks
+                .keys
+                .iter()
+                .map(|jwk| {
+                    let kid = jwk.common.key_id.clone();
+                    Ok((kid, DecodingKey::from_jwk(jwk)?))
+                })
+                .collect::<Result<_, ConfigurationError>>()?;
+            Keys::Jwks(keys)
+        } else if let Some(key_file) = &jwt_match.key_file {
+            let pem = read(key_file)?;
+            // the key type decides which algorithms verify, a public key is
+            // never usable as hmac secret
+            let key = DecodingKey::from_rsa_pem(&pem)
+                .or_else(|_| DecodingKey::from_ec_pem(&pem))
+                .or_else(|_| DecodingKey::from_ed_pem(&pem))?;
+            Keys::Key(key)
+        } else if let Some(secret) = &jwt_match.secret {
+            Keys::Key(DecodingKey::from_secret(secret.as_bytes()))
+        } else {
+            Keys::None
+        };
+        let claims = jwt_match
+            .claims
+            .iter()
+            .flatten()
+            .map(|(path, pattern)| Ok((path.clone(), Regex::new(pattern)?)))
+            .collect::<Result<_, ConfigurationError>>()?;
+
+        Ok(Verifier {
+            keys,
+            allow_expired: jwt_match.allow_expired.unwrap_or(false),
+            claims,
+        })
+    }
+
+    // claims of a token that is signed by the configured key and still
+    // valid
+    pub fn verify(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Value, ConfigurationError> {
+        let token =
+            bearer(headers).ok_or(ConfigurationError::RuleDoesNotMatch)?;
+        let header = jsonwebtoken::decode_header(token)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_aud = false;
+        validation.required_spec_claims.clear();
+        validation.validate_exp = !self.allow_expired;
+
+        let unchecked = DecodingKey::from_secret(&[]);
+        let key = match &self.keys {
+            Keys::Jwks(keys) => keys
+                .iter()
+                .find(|(kid, _)| {
+                    header.kid.is_none() || kid.as_ref() == header.kid.as_ref()
+                })
+                .map(|(_, key)| key)
+                .ok_or(ConfigurationError::Jwt(
+                    ErrorKind::InvalidKeyFormat.into(),
+                ))?,
+            Keys::Key(key) => key,
+            Keys::None => {
+                validation.insecure_disable_signature_validation();
+                &unchecked
+            }
+        };
+
+        Ok(jsonwebtoken::decode::<Value>(token, key, &validation)?.claims)
+    }
+
+    pub fn matches(&self, claims: &Value) -> bool {
+        self.claims.iter().all(|(path, regex)| {
+            match claims.dot_get::<Value>(path).ok().flatten() {
+                Some(Value::Array(items)) => {
+                    items.iter().any(|item| is_match(regex, item))
+                }
+                Some(value) => is_match(regex, &value),
+                None => false,
+            }
+        })
+    }
+}
+
+fn is_match(regex: &Regex, value: &Value) -> bool {
+    match value {
+        Value::String(value) => regex.is_match(value),
+        value => regex.is_match(&value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const RULE: &str = r#"
+name: jwt
+when:
+  matchesUris:
+    - uri: ^/
+  matchesJwt:
+then:
+  functionAs: Mock
+"#;
+
+    // verifier of a rule with this `matchesJwt` block
+    fn verifier(jwt: &str) -> Result<Arc<Verifier>, ConfigurationError> {
+        let when = format!("  matchesJwt:\n{jwt}\n");
+        let rule = Rule::from_yaml(&RULE.replace("  matchesJwt:\n", &when))?;
+        Ok(rule.state.jwt.unwrap())
+    }
+
+    fn headers(claims: &Value, secret: &str) -> HeaderMap {
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        let value = format!("Bearer {token}").parse().unwrap();
+        headers.insert(AUTHORIZATION, value);
+        headers
+    }
+
+    // seconds since the epoch, this far from now
+    fn at(offset: i64) -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_secs() as i64 + offset
+    }
+
+    #[test]
+    fn hmac_tokens_are_verified_with_the_secret() {
+        let verifier = verifier("    secret: s3cret").unwrap();
+        let claims = json!({ "sub": "ann", "exp": at(60) });
+        let verified = verifier.verify(&headers(&claims, "s3cret")).unwrap();
+        assert_eq!(verified, claims);
+        assert!(verifier.verify(&headers(&claims, "other")).is_err());
+        assert!(verifier.verify(&HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn expired_tokens_only_match_when_allowed() {
+        let claims = json!({ "sub": "ann", "exp": at(-3600) });
+        let strict = verifier("    secret: s3cret").unwrap();
+        assert!(strict.verify(&headers(&claims, "s3cret")).is_err());
+        let lenient =
+            verifier("    secret: s3cret\n    allowExpired: true").unwrap();
+        assert!(lenient.verify(&headers(&claims, "s3cret")).is_ok());
+    }
+
+    #[test]
+    fn unsigned_checks_read_any_signature() {
+        let verifier = verifier("    claims:\n      sub: ^ann$").unwrap();
+        let claims = json!({ "sub": "ann" });
+        let verified = verifier.verify(&headers(&claims, "any")).unwrap();
+        assert!(verifier.matches(&verified));
+    }
+
+    #[test]
+    fn claims_are_matched_by_dot_path() {
+        let verifier =
+            verifier("    claims:\n      roles: ^admin$\n      org.id: ^4\\d$")
+                .unwrap();
+        let claims = |roles: Value, id: Value| {
+            json!({ "roles": roles, "org": { "id": id } })
+        };
+        assert!(verifier.matches(&claims(json!(["user", "admin"]), json!(42))));
+        assert!(verifier.matches(&claims(json!("admin"), json!("43"))));
+        assert!(!verifier.matches(&claims(json!(["user"]), json!(42))));
+        assert!(!verifier.matches(&claims(json!("admin"), json!(52))));
+        assert!(!verifier.matches(&json!({ "roles": "admin" })));
+    }
+
+    #[test]
+    fn invalid_claim_patterns_fail_the_rule() {
+        assert!(verifier("    claims:\n      sub: (").is_err());
+        assert!(verifier("    keyFile: missing.pem").is_err());
+    }
+
+    #[test]
+    fn templates_see_the_claims_as_sent() {
+        let claims = json!({ "sub": "ann" });
+        let headers = headers(&claims, "unknown");
+        assert_eq!(unverified_claims(&headers), Some(claims));
+        assert_eq!(bearer(&HeaderMap::new()), None);
+    }
+}
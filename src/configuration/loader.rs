@@ -5,22 +5,22 @@ use std::path::PathBuf;
 use std::fs;
 use thiserror::Error;
 
+use super::rule::error::ConfigurationError;
 use super::ruleset::RuleSet;
 
 #[derive(Error, Debug)]
 pub enum DeserializationError {
-    #[error("Could not read directory")]
-    ReadDirectory(),
     #[error("could not parse regex")]
     Regex(#[from] regex::Error),
-    #[error("using forbidden extension")]
-    ForbiddenExtension,
-    #[error("has no extension")]
-    MissingExtension,
     #[error("could not read file")]
     IO(#[from] std::io::Error),
     #[error("could not parse yaml")]
     YamlParse(#[from] serde_yaml::Error),
+    #[error("invalid rule {name}: {source}")]
+    Rule {
+        name: String,
+        source: ConfigurationError,
+    },
 }
 
 pub struct YamlFileLoader {
@@ -35,12 +35,10 @@ impl YamlFileLoader {
         let regex_matcher = RegexSet::new(&self.extensions)?;
         let p = f?.path();
         let c = p.clone();
-        let ext = p
+        let matches_allowed_ext = p
             .extension()
-            .ok_or(DeserializationError::MissingExtension)?;
-        let matches_allowed_ext = regex_matcher.is_match(
-            ext.to_str().ok_or(DeserializationError::MissingExtension)?,
-        );
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| regex_matcher.is_match(ext));
 
         if matches_allowed_ext {
             let file_buffer = std::fs::File::open(p)?;
@@ -55,7 +53,9 @@ impl YamlFileLoader {
             }
             Ok(content)
         } else {
-            Err(DeserializationError::ForbiddenExtension)
+            // e.g. body files next to the rules referring to them
+            log::info!("skipping {}, not a rule file", c.display());
+            Ok(Vec::new())
         }
     }
 
@@ -78,6 +78,10 @@ impl YamlFileLoader {
         all_files.sort_by_key(|f| f.path());
 
         for file in all_files {
+            // subdirectories can hold files referenced by rules
+            if file.file_type()?.is_dir() {
+                continue;
+            }
             let deserialized_rules = self.deserialize_file(Ok(file))?;
             log::info!("deserialized rules: {:?}", deserialized_rules);
             dir_contents.extend(deserialized_rules);
@@ -85,3 +89,52 @@ impl YamlFileLoader {
         Ok(dir_contents)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = "- Rule:\n    name: file\n    when:\n      \
+        matchesUris:\n        - uri: ^/\n    then:\n      \
+        functionAs: Mock\n      bodyFile: body.json\n";
+
+    // loads a directory with these files, each test uses its own
+    fn load(
+        test: &str,
+        files: &[(&str, &str)],
+    ) -> Result<Vec<RuleSet>, DeserializationError> {
+        let dir = std::env::temp_dir()
+            .join(format!("fips-{test}-{}", std::process::id()));
+        fs::create_dir_all(dir.join("bodies")).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let loader = YamlFileLoader {
+            extensions: vec![String::from("yaml"), String::from("yml")],
+        };
+        let rules = loader.load_from_directories(std::slice::from_ref(&dir));
+        fs::remove_dir_all(dir).unwrap();
+        rules
+    }
+
+    #[test]
+    fn other_files_are_skipped() {
+        let rules = load(
+            "skipped",
+            &[
+                ("rules.yaml", RULE),
+                ("body.json", "{}"),
+                ("README", "no rules here"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 1);
+        let RuleSet::Rule(rule) = &rules[0];
+        assert!(rule.path.ends_with("rules.yaml"));
+    }
+
+    #[test]
+    fn invalid_rule_files_fail() {
+        assert!(load("invalid", &[("rules.yml", "- Rule: {}")]).is_err());
+    }
+}
@@ -1,7 +1,20 @@
-pub mod rulecontainer;
 pub mod ruleset;
 pub mod rule;
+#[allow(clippy::module_inception)]
 pub mod configuration;
 pub mod intermediary;
 pub mod loader;
 pub mod holder;
+pub mod balancer;
+pub mod transform;
+pub mod xml;
+pub mod form;
+pub mod template;
+pub mod cookie;
+pub mod graphql;
+pub mod grpc;
+pub mod seed;
+pub mod rate_limit;
+pub mod oauth;
+pub mod jwt;
+pub mod auth;
@@ -0,0 +1,144 @@
+use std::sync::OnceLock;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rcgen::KeyPair;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::rule::error::ConfigurationError;
+
+// one key per run, tokens stay valid when the configuration is reloaded
+static SIGNING_KEY: OnceLock<SigningKey> = OnceLock::new();
+
+struct SigningKey {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Value,
+    kid: String,
+}
+
+impl SigningKey {
+    fn generate() -> Result<SigningKey, ConfigurationError> {
+        let key = KeyPair::generate()
+            .map_err(|e| ConfigurationError::OAuth(e.to_string()))?;
+        // an uncompressed P-256 point, 0x04 followed by x and y
+        let point = key.public_key_raw();
+        let x = URL_SAFE_NO_PAD.encode(&point[1..33]);
+        let y = URL_SAFE_NO_PAD.encode(&point[33..65]);
+
+        // RFC 7638 thumbprint, members in lexicographic order
+        let thumbprint = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#
+        );
+        let kid = URL_SAFE_NO_PAD.encode(Sha256::digest(thumbprint));
+
+        Ok(SigningKey {
+            encoding: EncodingKey::from_ec_pem(key.serialize_pem().as_bytes())?,
+            decoding: DecodingKey::from_ec_components(&x, &y)?,
+            jwk: json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "use": "sig",
+                "alg": "ES256",
+                "kid": kid,
+                "x": x,
+                "y": y,
+            }),
+            kid,
+        })
+    }
+}
+
+// generates the signing key when the first oauth rule is loaded
+pub fn init() -> Result<(), ConfigurationError> {
+    if SIGNING_KEY.get().is_none() {
+        // a key generated concurrently is as good as this one
+        let _ = SIGNING_KEY.set(SigningKey::generate()?);
+    }
+    Ok(())
+}
+
+fn signing_key() -> Result<&'static SigningKey, ConfigurationError> {
+    SIGNING_KEY.get().ok_or_else(|| {
+        ConfigurationError::OAuth(String::from("no signing key generated"))
+    })
+}
+
+pub fn sign(claims: &Value) -> Result<String, ConfigurationError> {
+    let key = signing_key()?;
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(key.kid.clone());
+    Ok(jsonwebtoken::encode(&header, claims, &key.encoding)?)
+}
+
+// claims of a token signed by fips that has not expired
+pub fn verify(token: &str) -> Result<Value, ConfigurationError> {
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.validate_aud = false;
+    Ok(jsonwebtoken::decode::<Value>(
+        token,
+        &signing_key()?.decoding,
+        &validation,
+    )?
+    .claims)
+}
+
+pub fn jwks() -> Result<Value, ConfigurationError> {
+    Ok(json!({ "keys": [signing_key()?.jwk.clone()] }))
+}
+
+// RFC 7636, clients not sending a method use `plain`
+pub fn pkce_matches(
+    verifier: &str,
+    challenge: &str,
+    method: Option<&str>,
+) -> bool {
+    match method.unwrap_or("plain") {
+        "S256" => URL_SAFE_NO_PAD.encode(Sha256::digest(verifier)) == challenge,
+        "plain" => verifier == challenge,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_tokens_are_verified() {
+        init().unwrap();
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        let token = sign(&json!({ "sub": "ann", "exp": exp })).unwrap();
+        assert_eq!(verify(&token).unwrap()["sub"], "ann");
+        let expired = sign(&json!({ "sub": "ann", "exp": 1 })).unwrap();
+        assert!(verify(&expired).is_err());
+        let mut tampered = token.clone();
+        tampered.insert(tampered.len() - 2, 'x');
+        assert!(verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn the_jwks_holds_the_signing_key() {
+        init().unwrap();
+        let token = sign(&json!({ "exp": 4102444800u64 })).unwrap();
+        let kid = jsonwebtoken::decode_header(&token).unwrap().kid.unwrap();
+        let jwks = jwks().unwrap();
+        assert_eq!(jwks["keys"][0]["kid"], kid);
+        assert_eq!(jwks["keys"][0]["alg"], "ES256");
+    }
+
+    #[test]
+    fn pkce_challenges_are_checked() {
+        // RFC 7636, appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(pkce_matches(verifier, challenge, Some("S256")));
+        assert!(!pkce_matches(verifier, verifier, Some("S256")));
+        assert!(pkce_matches(verifier, verifier, None));
+        assert!(!pkce_matches(verifier, challenge, Some("plain")));
+        assert!(!pkce_matches(verifier, verifier, Some("S512")));
+    }
+}
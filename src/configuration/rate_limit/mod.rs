@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http::{header::RETRY_AFTER, HeaderMap, HeaderValue};
+
+use super::configuration::{RateLimit, RateLimitKey, RateLimitStrategy};
+use super::intermediary::Intermediary;
+
+// the requests every client has left
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // requests in the window, or tokens left in the bucket
+    level: f64,
+    since: Instant,
+}
+
+/// Outcome of counting one request
+#[derive(Debug, Clone)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Until the quota is fully available again
+    pub reset: Duration,
+    /// Until the next request is allowed
+    pub retry_after: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> RateLimiter {
+        RateLimiter {
+            config: config.clone(),
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn check(&self, intermediary: &Intermediary) -> Quota {
+        let now = Instant::now();
+        let limit = self.config.limit as f64;
+        let window = Duration::from_millis(self.config.window.max(1));
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // a bucket untouched for a window is as good as a new one, so they
+        // are dropped once per window to keep clients from piling up
+        if now.duration_since(buckets.pruned) >= window {
+            buckets
+                .by_key
+                .retain(|_, bucket| now.duration_since(bucket.since) < window);
+            buckets.pruned = now;
+        }
+        let buckets = &mut buckets.by_key;
+
+        match self.config.strategy.clone().unwrap_or_default() {
+            RateLimitStrategy::FixedWindow => {
+                let bucket =
+                    buckets.entry(self.key(intermediary)).or_insert(Bucket {
+                        level: 0.0,
+                        since: now,
+                    });
+                if now.duration_since(bucket.since) >= window {
+                    bucket.level = 0.0;
+                    bucket.since = now;
+                }
+                let allowed = bucket.level < limit;
+                if allowed {
+                    bucket.level += 1.0;
+                }
+                let reset = window - now.duration_since(bucket.since);
+                Quota {
+                    allowed,
+                    limit: self.config.limit,
+                    remaining: (limit - bucket.level) as u64,
+                    reset,
+                    retry_after: reset,
+                }
+            }
+            RateLimitStrategy::TokenBucket => {
+                // tokens trickle back in, a full bucket takes one window
+                let per_token = window.as_secs_f64() / limit.max(1.0);
+                let bucket =
+                    buckets.entry(self.key(intermediary)).or_insert(Bucket {
+                        level: limit,
+                        since: now,
+                    });
+                let refilled =
+                    now.duration_since(bucket.since).as_secs_f64() / per_token;
+                bucket.level = (bucket.level + refilled).min(limit);
+                bucket.since = now;
+                let allowed = bucket.level >= 1.0;
+                if allowed {
+                    bucket.level -= 1.0;
+                }
+                Quota {
+                    allowed,
+                    limit: self.config.limit,
+                    remaining: bucket.level as u64,
+                    reset: Duration::from_secs_f64(
+                        (limit - bucket.level) * per_token,
+                    ),
+                    retry_after: Duration::from_secs_f64(
+                        (1.0 - bucket.level).max(0.0) * per_token,
+                    ),
+                }
+            }
+        }
+    }
+
+    fn key(&self, intermediary: &Intermediary) -> String {
+        let key = match self.config.key.as_ref().unwrap_or(&RateLimitKey::Ip)
+        {
+            RateLimitKey::Ip => {
+                intermediary.client.map(|client| client.ip().to_string())
+            }
+            RateLimitKey::Header(name) => intermediary
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            RateLimitKey::Query(name) => intermediary
+                .uri
+                .as_ref()
+                .and_then(|uri| uri.query())
+                .and_then(|query| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.into_owned())
+                }),
+        };
+        key.unwrap_or_default()
+    }
+}
+
+impl Quota {
+    // headers as sent by most public apis, times in whole seconds
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers
+            .insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert(
+            "x-ratelimit-reset",
+            HeaderValue::from(seconds(self.reset)),
+        );
+        if !self.allowed {
+            headers.insert(
+                RETRY_AFTER,
+                HeaderValue::from(seconds(self.retry_after)),
+            );
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(1000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::*;
+
+    fn limiter(yaml: &str) -> RateLimiter {
+        RateLimiter::new(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    fn request(uri: &str, api_key: Option<&str>) -> Intermediary {
+        let mut request = Intermediary::request(Method::GET, uri, b"");
+        if let Some(api_key) = api_key {
+            request
+                .headers
+                .insert("x-api-key", api_key.parse().unwrap());
+        }
+        request
+    }
+
+    fn allowed(limiter: &RateLimiter, request: &Intermediary) -> Vec<bool> {
+        (0..3).map(|_| limiter.check(request).allowed).collect()
+    }
+
+    #[test]
+    fn fixed_windows_reset_after_the_window() {
+        let limiter = limiter("limit: 2\nwindow: 100");
+        let request = request("/", None);
+        let quota = limiter.check(&request);
+        assert_eq!((quota.allowed, quota.remaining), (true, 1));
+        assert!(limiter.check(&request).allowed);
+        let quota = limiter.check(&request);
+        assert!(!quota.allowed);
+        assert!(quota.retry_after <= Duration::from_millis(100));
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(limiter.check(&request).allowed);
+    }
+
+    #[test]
+    fn token_buckets_refill_over_the_window() {
+        let limiter = limiter("limit: 2\nwindow: 100\nstrategy: TokenBucket");
+        let request = request("/", None);
+        assert_eq!(allowed(&limiter, &request), [true, true, false]);
+        // a token comes back every 50ms
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(allowed(&limiter, &request), [true, false, false]);
+    }
+
+    #[test]
+    fn keys_have_their_own_quota() {
+        let by_header =
+            limiter("limit: 1\nwindow: 60000\nkey: !Header x-api-key");
+        assert!(by_header.check(&request("/", Some("a"))).allowed);
+        assert!(by_header.check(&request("/", Some("b"))).allowed);
+        assert!(!by_header.check(&request("/", Some("a"))).allowed);
+
+        let by_query = limiter("limit: 1\nwindow: 60000\nkey: { Query: key }");
+        assert!(by_query.check(&request("/?key=a", None)).allowed);
+        assert!(by_query.check(&request("/?key=b", None)).allowed);
+        assert!(!by_query.check(&request("/?key=a&x=1", None)).allowed);
+    }
+
+    #[test]
+    fn quotas_are_sent_as_headers() {
+        let limiter = limiter("limit: 1\nwindow: 1500");
+        let request = request("/", None);
+        let mut headers = HeaderMap::new();
+        limiter.check(&request).apply(&mut headers);
+        assert_eq!(headers["x-ratelimit-limit"], "1");
+        assert_eq!(headers["x-ratelimit-remaining"], "0");
+        assert_eq!(headers["x-ratelimit-reset"], "2");
+        assert!(headers.get(RETRY_AFTER).is_none());
+        limiter.check(&request).apply(&mut headers);
+        assert_eq!(headers[RETRY_AFTER], "2");
+    }
+}
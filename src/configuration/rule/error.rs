@@ -27,6 +27,30 @@ pub enum ConfigurationError {
     Std(#[from] std::io::Error),
     #[error("hyper lib error")]
     Hyper(#[from] hyper::Error),
+    #[error("Invalid 'at' in rule: {0}")]
+    DotPath(#[from] json_dotpath::Error),
+    #[error("Invalid regex in rule: {0}")]
+    Regex(#[from] regex::Error),
+    #[error("Invalid base64 body in rule: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Not a redirect status: {0}")]
+    InvalidRedirectStatus(String),
+    #[error("Invalid graphql: {0}")]
+    GraphQL(String),
+    #[error("Invalid grpc: {0}")]
+    Grpc(String),
+    #[error("Not a duration or RFC 3339 timestamp: {0}")]
+    InvalidTime(String),
+    #[error("OAuth error: {0}")]
+    OAuth(String),
+    #[error("Invalid key file: {0}")]
+    KeyFile(String),
+    #[error("Invalid token: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+    #[error("Invalid xml: {0}")]
+    Xml(String),
+    #[error("Could not transform body: {0}")]
+    Transform(String),
     #[error("rule does not match")]
     RuleDoesNotMatch,
 }
@@ -1,4 +1,5 @@
 pub mod error;
+pub mod state;
 pub mod then;
 pub mod when;
 pub mod with;
@@ -9,11 +10,20 @@ use serde::{Deserialize, Serialize};
 use eyre::{ContextCompat, Result};
 use regex::RegexSet;
 use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
 
+use super::configuration::Auth;
+use super::rule::state::RuleState;
 use super::rule::then::Then;
 use super::rule::when::When;
 use super::rule::with::With;
 use super::intermediary::Intermediary;
+use super::cookie;
+use super::graphql::Operation;
+use super::grpc;
+use super::xml;
 
 use crate::plugin_registry::ExternalFunctions;
 
@@ -23,16 +33,18 @@ pub struct Rule {
     pub when: When,
     pub then: Then,
     pub with: Option<With>,
+    /// Requests without valid credentials are rejected instead
+    pub auth: Option<Auth>,
     #[serde(skip)]
     pub path: String,
     #[serde(skip)]
     pub plugins: Option<ExternalFunctions>,
+    #[serde(skip)]
+    pub state: RuleState,
 }
 
 impl Rule {
     pub fn should_apply(&self, intermediary: &Intermediary) -> Result<()> {
-        let mut rng = rand::thread_rng();
-
         let uri_regex = RegexSet::new(
             self.when
                 .matches
@@ -46,16 +58,29 @@ impl Rule {
             .as_ref()
             .wrap_err("could not retrieve uri")?;
 
-        let some_uris_match = uri_regex.is_match(uri.path());
+        // requests sent to fips as proxy also match by their full url
+        let some_uris_match = uri_regex.is_match(uri.path())
+            || uri.authority().is_some_and(|authority| {
+                let scheme = uri.scheme_str().unwrap_or("http");
+                let url = format!("{scheme}://{authority}{}", uri.path());
+                uri_regex.is_match(&url)
+            });
         if !some_uris_match {
             return Err(ConfigurationError::RuleDoesNotMatch.into());
         }
 
+        if let Some(hosts) = &self.state.hosts {
+            let host = intermediary.host().unwrap_or_default();
+            if !hosts.iter().any(|regex| regex.is_match(&host)) {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
         let some_methods_match =
-            self.when.matches_methods.as_ref().map_or(true, |methods| {
+            self.when.matches_methods.as_ref().is_none_or(|methods| {
                 methods
                     .iter()
-                    .any(|m| intermediary.method.as_ref().map_or(false, |method| m == method.as_str()))
+                    .any(|m| intermediary.method.as_ref().is_some_and(|method| m == method.as_str()))
             });
 
         if !some_methods_match {
@@ -66,14 +91,72 @@ impl Rule {
             self.when
                 .body_contains
                 .as_ref()
-                .map_or(true, |body_contains| {
-                    intermediary.body.as_str().unwrap().contains(body_contains)
+                .is_none_or(|body_contains| {
+                    intermediary.body_text().contains(body_contains)
                 });
 
         if !some_body_contains {
             return Err(ConfigurationError::RuleDoesNotMatch.into());
         }
 
+        if let (Some(xpath_matches), Some(xpaths)) =
+            (&self.when.matches_xpath, &self.state.xpaths)
+        {
+            let body = intermediary.body_text();
+            if !xml::matches(&body, xpath_matches, xpaths)? {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Some(regex) = &self.state.soap_action {
+            let action_matches = xml::soap_action(&intermediary.headers)
+                .is_some_and(|action| regex.is_match(&action));
+            if !action_matches {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Some(patterns) = &self.state.graphql {
+            let operation_matches = Operation::from_request(intermediary)
+                .is_some_and(|operation| operation.matches(patterns));
+            if !operation_matches {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Then::Grpc {
+            service, method, ..
+        } = &self.then
+        {
+            let method_matches = grpc::matches_method(
+                uri.path(),
+                service.as_deref(),
+                method.as_deref(),
+            );
+            if !method_matches {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Some(patterns) = &self.state.form {
+            if !patterns.matches(intermediary.form.as_ref()) {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Some(cookies) = &self.state.cookies {
+            if !cookie::matches(&intermediary.headers, cookies) {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
+        if let Some(verifier) = &self.state.jwt {
+            let claims = verifier.verify(&intermediary.headers)?;
+            if !verifier.matches(&claims) {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
         let probability_matches = self
             .with
             .as_ref()
@@ -81,11 +164,13 @@ impl Rule {
                 probability: Some(1.0),
                 plugins: None,
                 sleep: None,
+                jitter: None,
+                seed: None,
+                rate_limit: None,
             })
             .probability
             .map(|probability| {
-                let random_number = rng.gen_range(0.0, 0.99);
-                random_number < probability
+                self.state.rng.lock().unwrap().gen::<f32>() < probability
             })
             .unwrap_or(true);
 
@@ -93,6 +178,188 @@ impl Rule {
             return Err(ConfigurationError::RuleDoesNotMatch.into());
         }
 
+        let now = SystemTime::now();
+        let active = self.state.active_from.is_none_or(|from| now >= from)
+            && self.state.active_until.is_none_or(|until| now < until);
+        if !active {
+            return Err(ConfigurationError::RuleDoesNotMatch.into());
+        }
+
+        // hits are counted last, only requests the rule matches count
+        if self.when.times.is_some() || self.when.after_hits.is_some() {
+            let hit = self.state.hits.fetch_add(1, Ordering::SeqCst) + 1;
+            let after_hits = self.when.after_hits.unwrap_or(0);
+            let hit_applies = hit > after_hits
+                && self
+                    .when
+                    .times
+                    .is_none_or(|times| hit <= after_hits + times);
+            if !hit_applies {
+                return Err(ConfigurationError::RuleDoesNotMatch.into());
+            }
+        }
+
         Ok(())
     }
+
+    // how long to wait before answering, jitter is drawn from the rule's
+    // seeded random numbers
+    pub fn delay(&self) -> Option<Duration> {
+        let with = self.with.as_ref()?;
+        let jitter = with.jitter.map_or(0, |jitter| {
+            self.state.rng.lock().unwrap().gen_range(0, jitter + 1)
+        });
+        match with.sleep.unwrap_or(0) + jitter {
+            0 => None,
+            delay => Some(Duration::from_millis(delay)),
+        }
+    }
+
+    // files referenced by a rule are relative to the file it was loaded from
+    pub fn relative_path(&self, file: &str) -> PathBuf {
+        Path::new(&self.path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(file)
+    }
+}
+
+#[cfg(test)]
+impl Rule {
+    // a rule set up the way the loader does it
+    pub fn from_yaml(yaml: &str) -> Result<Rule, ConfigurationError> {
+        let mut rule: Rule = serde_yaml::from_str(yaml)?;
+        rule.state = RuleState::new(&rule, 0)?;
+        Ok(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+
+    const RULE: &str = r#"
+name: rule
+when:
+  matchesUris:
+    - uri: ^/api
+then:
+  functionAs: Mock
+"#;
+
+    // rule with these additional `when` conditions
+    fn rule(when: &str) -> Result<Rule, ConfigurationError> {
+        Rule::from_yaml(&RULE.replace("then:", &format!("{when}\nthen:")))
+    }
+
+    fn applies(rule: &Rule, uri: &str) -> bool {
+        let request = Intermediary::request(Method::GET, uri, b"");
+        rule.should_apply(&request).is_ok()
+    }
+
+    #[test]
+    fn only_matching_uris_apply() {
+        let rule = rule("").unwrap();
+        assert!(applies(&rule, "/api/users"));
+        assert!(!applies(&rule, "/other"));
+    }
+
+    #[test]
+    fn hits_are_counted_for_matching_requests() {
+        let rule = rule("  afterHits: 1\n  times: 2").unwrap();
+        let hits = (0..4).map(|_| applies(&rule, "/api")).collect::<Vec<_>>();
+        assert_eq!(hits, [false, true, true, false]);
+        // requests the rule does not match are not counted
+        assert!(!applies(&rule, "/other"));
+        assert_eq!(rule.state.hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn rules_apply_in_their_time_window() {
+        let active = rule("  activeFrom: 0s\n  activeUntil: 1h").unwrap();
+        assert!(applies(&active, "/api"));
+        let later = rule("  activeFrom: 1h").unwrap();
+        assert!(!applies(&later, "/api"));
+        let ended = rule("  activeUntil: 2020-01-01T00:00:00Z").unwrap();
+        assert!(!applies(&ended, "/api"));
+        let started = rule("  activeFrom: 2020-01-01T00:00:00Z").unwrap();
+        assert!(applies(&started, "/api"));
+    }
+
+    #[test]
+    fn times_are_parsed_when_the_rule_is_loaded() {
+        let rule = rule("  activeFrom: 1h").unwrap();
+        let from = rule.state.active_from.unwrap();
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        assert!(from <= in_an_hour);
+        assert!(from > in_an_hour - Duration::from_secs(60));
+    }
+
+    #[test]
+    fn invalid_times_fail_the_rule() {
+        assert!(matches!(
+            rule("  activeFrom: tomorrow"),
+            Err(ConfigurationError::InvalidTime(_))
+        ));
+        assert!(rule("  activeUntil: 2020-13-01T00:00:00Z").is_err());
+    }
+
+    fn applies_to_host(rule: &Rule, host: &str) -> bool {
+        let mut request = Intermediary::request(Method::GET, "/api", b"");
+        request
+            .headers
+            .insert(http::header::HOST, host.parse().unwrap());
+        rule.should_apply(&request).is_ok()
+    }
+
+    #[test]
+    fn hosts_match_by_name_wildcard_or_regex() {
+        let rule = rule(
+            "  matchesHost:\n    - api.local\n    - \"*.cdn.local\"\n    \
+                - regex: ^auth\\d*\\.local$",
+        )
+        .unwrap();
+        assert!(applies_to_host(&rule, "api.local"));
+        assert!(applies_to_host(&rule, "API.local:8080"));
+        assert!(applies_to_host(&rule, "img.cdn.local"));
+        assert!(applies_to_host(&rule, "auth2.local"));
+        assert!(!applies_to_host(&rule, "api.local.evil"));
+        assert!(!applies_to_host(&rule, "cdn.local"));
+        assert!(!applies_to_host(&rule, "other.local"));
+    }
+
+    #[test]
+    fn proxied_requests_match_by_their_authority() {
+        let rule = rule("  matchesHost:\n    - api.local").unwrap();
+        assert!(applies(&rule, "http://api.local/api"));
+        assert!(!applies(&rule, "http://other.local/api"));
+    }
+
+    #[test]
+    fn invalid_host_patterns_fail_the_rule() {
+        assert!(rule("  matchesHost:\n    - regex: (api").is_err());
+    }
+
+    // what a rule seeded with 7 decides for ten requests
+    fn decisions(with: &str) -> (Vec<bool>, Vec<Option<Duration>>) {
+        let yaml = format!("{RULE}with:\n  seed: 7\n{with}");
+        let rule = Rule::from_yaml(&yaml).unwrap();
+        let applied = (0..10).map(|_| applies(&rule, "/api")).collect();
+        let delays = (0..10).map(|_| rule.delay()).collect();
+        (applied, delays)
+    }
+
+    #[test]
+    fn seeded_rules_decide_the_same_every_run() {
+        let with = "  probability: 0.5\n  sleep: 10\n  jitter: 100\n";
+        let (applied, delays) = decisions(with);
+        assert_eq!((applied.clone(), delays.clone()), decisions(with));
+        assert!(applied.contains(&true) && applied.contains(&false));
+        assert!(delays
+            .iter()
+            .flatten()
+            .all(|delay| { (10..=110).contains(&delay.as_millis()) }));
+    }
 }
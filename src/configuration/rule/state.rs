@@ -0,0 +1,199 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use prost_reflect::DescriptorPool;
+use regex::Regex;
+
+use super::super::balancer::Balancer;
+use super::super::configuration::HostMatch;
+use super::super::cookie;
+use super::super::form::{self, FormPatterns};
+use super::super::graphql::{self, GraphQLPatterns};
+use super::super::grpc;
+use super::super::oauth;
+use super::super::jwt;
+use super::super::rate_limit::RateLimiter;
+use super::super::seed::{self, SharedRng};
+use super::super::transform;
+use super::super::xml;
+use super::error::ConfigurationError;
+use super::then::{self, Then};
+use super::Rule;
+
+// runtime data of a rule that is not part of its configuration
+#[derive(Debug, Clone)]
+pub struct RuleState {
+    pub balancer: Option<Arc<Balancer>>,
+    /// Requests the rule matched so far, shared by all copies of the rule
+    pub hits: Arc<AtomicU64>,
+    /// Start and end of `activeFrom` and `activeUntil`
+    pub active_from: Option<SystemTime>,
+    pub active_until: Option<SystemTime>,
+    /// Source of every random decision about the rule
+    pub rng: SharedRng,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Services of a grpc rule, compiled once when the rule is loaded
+    pub descriptors: Option<DescriptorPool>,
+    /// The `transform` filter of the response modifications
+    pub jq: Option<Arc<transform::Jq>>,
+    /// Regexes of the `text` response modifications
+    pub text: Option<Arc<transform::TextPatterns>>,
+    /// Regexes of `matchesHost`
+    pub hosts: Option<Arc<Vec<Regex>>>,
+    /// Compiled xpaths of `matchesXPath` and the xml modifications
+    pub xpaths: Option<Arc<xml::XPaths>>,
+    /// Value regexes of `matchesCookies`, by cookie name
+    pub cookies: Option<Arc<Vec<(String, Regex)>>>,
+    /// Regex of `matchesSoapAction`
+    pub soap_action: Option<Regex>,
+    /// Regexes of `matchesForm` and `matchesFiles`
+    pub form: Option<Arc<FormPatterns>>,
+    /// Regexes of `matchesGraphQL`
+    pub graphql: Option<Arc<GraphQLPatterns>>,
+    /// The `schemaFile` of a graphql rule
+    pub schema: Option<Arc<graphql::Schema>>,
+    /// Regexes of the messages a websocket rule replies to
+    pub replies: Option<Arc<Vec<Regex>>>,
+    /// Keys and claim patterns of `matchesJwt`
+    pub jwt: Option<Arc<jwt::Verifier>>,
+}
+
+impl Default for RuleState {
+    fn default() -> Self {
+        RuleState {
+            balancer: None,
+            hits: Arc::default(),
+            active_from: None,
+            active_until: None,
+            rng: seed::rng(None, 0),
+            rate_limiter: None,
+            descriptors: None,
+            jq: None,
+            text: None,
+            hosts: None,
+            xpaths: None,
+            cookies: None,
+            soap_action: None,
+            form: None,
+            graphql: None,
+            schema: None,
+            replies: None,
+            jwt: None,
+        }
+    }
+}
+
+impl RuleState {
+    pub fn new(
+        rule: &Rule,
+        position: usize,
+    ) -> Result<RuleState, ConfigurationError> {
+        let loaded_at = SystemTime::now();
+        let point_in_time = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| point_in_time(value, loaded_at))
+                .transpose()
+        };
+        let rng = seed::rng(rule.with.as_ref().and_then(|w| w.seed), position);
+        let balancer = rule.then.forward_uri().and_then(|forward_uri| {
+            Balancer::new(forward_uri, rule.then.load_balancing(), rng.clone())
+        });
+        let rate_limiter = rule
+            .with
+            .as_ref()
+            .and_then(|with| with.rate_limit.as_ref())
+            .map(|rate_limit| Arc::new(RateLimiter::new(rate_limit)));
+        if let Then::OAuth { .. } = rule.then {
+            oauth::init()?;
+        }
+        if let Then::Redirect { status, .. } = &rule.then {
+            then::redirect_status(status.as_deref())?;
+        }
+        let hosts = rule
+            .when
+            .matches_host
+            .as_ref()
+            .map(|hosts| hosts.iter().map(HostMatch::regex).collect())
+            .transpose()?
+            .map(Arc::new);
+        let modify_response = rule.then.modify_response();
+        if let Some(patch) = modify_response.and_then(|m| m.patch.as_ref()) {
+            transform::check_patch(patch)?;
+        }
+        let jq = modify_response
+            .and_then(|modify| modify.transform.as_deref())
+            .map(|filter| transform::Jq::new(filter).map(Arc::new))
+            .transpose()?;
+        let text = modify_response
+            .and_then(|modify| modify.text.as_deref())
+            .map(|text| transform::TextPatterns::new(text).map(Arc::new))
+            .transpose()?;
+        let replies = match &rule.then {
+            Then::WebSocket {
+                replies: Some(replies),
+                ..
+            } => Some(Arc::new(
+                replies
+                    .iter()
+                    .map(|reply| Regex::new(&reply.matches))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => None,
+        };
+        let jwt = rule
+            .when
+            .matches_jwt
+            .as_ref()
+            .map(|jwt_match| jwt::Verifier::new(jwt_match, rule).map(Arc::new))
+            .transpose()?;
+        Ok(RuleState {
+            balancer,
+            active_from: point_in_time(&rule.when.active_from)?,
+            active_until: point_in_time(&rule.when.active_until)?,
+            rng,
+            rate_limiter,
+            descriptors: grpc::load_rule(rule)?,
+            jq,
+            text,
+            hosts,
+            xpaths: xml::load_rule(rule)?.map(Arc::new),
+            cookies: rule
+                .when
+                .matches_cookies
+                .as_ref()
+                .map(|cookies| cookie::patterns(cookies).map(Arc::new))
+                .transpose()?,
+            soap_action: rule
+                .when
+                .matches_soap_action
+                .as_deref()
+                .map(Regex::new)
+                .transpose()?,
+            form: form::load_rule(rule)?.map(Arc::new),
+            graphql: rule
+                .when
+                .matches_graphql
+                .as_ref()
+                .map(|graphql| GraphQLPatterns::new(graphql).map(Arc::new))
+                .transpose()?,
+            schema: graphql::load_rule(rule)?.map(Arc::new),
+            replies,
+            jwt,
+            ..RuleState::default()
+        })
+    }
+}
+
+// durations count from when the rules were loaded
+fn point_in_time(
+    value: &str,
+    loaded_at: SystemTime,
+) -> Result<SystemTime, ConfigurationError> {
+    match humantime::parse_duration(value) {
+        Ok(duration) => Ok(loaded_at + duration),
+        Err(_) => humantime::parse_rfc3339_weak(value)
+            .map_err(|_| ConfigurationError::InvalidTime(value.to_string())),
+    }
+}
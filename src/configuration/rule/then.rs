@@ -1,31 +1,223 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use schemars::JsonSchema;
 
-use super::super::configuration::{ModifyResponseFips, ModifyResponseProxy};
+use super::super::configuration::{
+    BodyEncoding, DeleteCookie, Fallback, ForwardUri, HeaderValues,
+    LoadBalancing, ModifyRequest, ModifyResponseFips, ModifyResponseProxy,
+    OAuthClient, ServerSentEvent, SetCookie, WebSocketClose, WebSocketPush,
+    WebSocketReply,
+};
+use super::error::ConfigurationError;
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "functionAs")]
 pub enum Then {
     Fips {
         #[serde(rename = "forwardUri")]
-        forward_uri: String,
+        forward_uri: ForwardUri,
+        #[serde(rename = "loadBalancing")]
+        load_balancing: Option<LoadBalancing>,
+        #[serde(rename = "modifyRequest")]
+        modify_request: Option<ModifyRequest>,
         #[serde(rename = "modifyResponse")]
         modify_response: Option<ModifyResponseFips>,
+        fallback: Option<Fallback>,
     },
     Proxy {
         #[serde(rename = "forwardUri")]
-        forward_uri: String,
+        forward_uri: ForwardUri,
+        #[serde(rename = "loadBalancing")]
+        load_balancing: Option<LoadBalancing>,
+        #[serde(rename = "modifyRequest")]
+        modify_request: Option<ModifyRequest>,
         modify_response: Option<ModifyResponseProxy>,
+        fallback: Option<Fallback>,
     },
     Static {
         #[serde(rename = "baseDir")]
         static_base_dir: Option<String>,
     },
+    Redirect {
+        /// Value of the Location header, can refer to the request and the
+        /// groups captured by `matchesUris`
+        location: String,
+        /// One of 301, 302 (default), 303, 307 or 308
+        status: Option<String>,
+        /// Append the query string of the request to the location
+        #[serde(rename = "preserveQuery")]
+        preserve_query: Option<bool>,
+        headers: Option<HashMap<String, HeaderValues>>,
+    },
+    WebSocket {
+        /// Messages sent once the connection is established
+        #[serde(rename = "onConnect")]
+        on_connect: Option<Vec<Value>>,
+        replies: Option<Vec<WebSocketReply>>,
+        pushes: Option<Vec<WebSocketPush>>,
+        close: Option<WebSocketClose>,
+        /// Tunnel the connection to this websocket instead of playing the
+        /// script
+        #[serde(rename = "forwardUri")]
+        forward_uri: Option<String>,
+    },
+    EventStream {
+        events: Vec<ServerSentEvent>,
+        /// Times the events are played, 0 repeats them forever
+        repeat: Option<u64>,
+        /// End the response after the last event instead of holding the
+        /// connection open
+        close: Option<bool>,
+        headers: Option<HashMap<String, HeaderValues>>,
+    },
+    GraphQL {
+        /// SDL file answers are shaped by, relative to the rule file
+        #[serde(rename = "schemaFile")]
+        schema_file: Option<String>,
+        /// Values of fields by `Type.field`, objects provide their fields
+        resolvers: Option<HashMap<String, Value>>,
+        /// Static `data` answers by operation name
+        data: Option<HashMap<String, Value>>,
+        /// Operations answered by neither `data` nor a resolver of one of
+        /// their root fields are forwarded to this uri
+        #[serde(rename = "forwardUri")]
+        forward_uri: Option<String>,
+    },
+    Grpc {
+        /// Proto files describing the services, relative to the rule file
+        #[serde(rename = "protoFiles")]
+        proto_files: Option<Vec<String>>,
+        /// Directories imports are looked up in, the rule file's directory
+        /// by default
+        #[serde(rename = "importPaths")]
+        import_paths: Option<Vec<String>>,
+        /// Compiled descriptor set, e.g. from `protoc --descriptor_set_out`
+        #[serde(rename = "descriptorSet")]
+        descriptor_set: Option<String>,
+        /// Service name, with or without its package
+        service: Option<String>,
+        method: Option<String>,
+        /// Json of the response message, an array sends one message per item
+        /// for server streaming methods
+        response: Option<Value>,
+        /// gRPC status code, 0 (OK) by default
+        status: Option<u32>,
+        message: Option<String>,
+        headers: Option<HashMap<String, HeaderValues>>,
+        trailers: Option<HashMap<String, HeaderValues>>,
+    },
+    /// A local OpenID Connect provider, its endpoints are found below the
+    /// path the rule matches, e.g. `/auth/token`
+    OAuth {
+        /// The url tokens are issued by, where the provider is reached by
+        /// default
+        issuer: Option<String>,
+        /// Any client is served when not given
+        clients: Option<Vec<OAuthClient>>,
+        /// Claims of the signed in user, in id and access tokens and the
+        /// userinfo answer
+        claims: Option<serde_json::Map<String, Value>>,
+        /// Seconds tokens are valid, 3600 by default
+        #[serde(rename = "expiresIn")]
+        expires_in: Option<u64>,
+    },
     Mock {
         body: Option<Value>,
+        /// Served instead of `body`, relative to the rule file
+        #[serde(rename = "bodyFile")]
+        body_file: Option<String>,
+        /// Replace request placeholders in the body file
+        template: Option<bool>,
+        /// Non json content types serve a string body as it is
+        #[serde(rename = "contentType")]
+        content_type: Option<String>,
+        /// How a string body is decoded before it is served
+        encoding: Option<BodyEncoding>,
         status: Option<String>,
-        headers: Option<HashMap<String, String>>,
+        /// Replace headers of the same name, e.g. the content type
+        headers: Option<HashMap<String, HeaderValues>>,
+        /// Add to headers of the same name
+        #[serde(rename = "appendHeaders")]
+        append_headers: Option<HashMap<String, HeaderValues>>,
+        #[serde(rename = "setCookies")]
+        set_cookies: Option<Vec<SetCookie>>,
+        /// Cookies the client is told to remove
+        #[serde(rename = "deleteCookies")]
+        delete_cookies: Option<Vec<DeleteCookie>>,
     },
 }
+
+impl Then {
+    pub fn forward_uri(&self) -> Option<&ForwardUri> {
+        match self {
+            Then::Fips { forward_uri, .. }
+            | Then::Proxy { forward_uri, .. } => Some(forward_uri),
+            _ => None,
+        }
+    }
+
+    pub fn load_balancing(&self) -> Option<&LoadBalancing> {
+        match self {
+            Then::Fips { load_balancing, .. }
+            | Then::Proxy { load_balancing, .. } => load_balancing.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn modify_response(&self) -> Option<&ModifyResponseFips> {
+        match self {
+            Then::Fips {
+                modify_response, ..
+            } => modify_response.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn fallback(&self) -> Option<&Fallback> {
+        match self {
+            Then::Fips { fallback, .. } | Then::Proxy { fallback, .. } => {
+                fallback.as_ref()
+            }
+            _ => None,
+        }
+    }
+}
+
+// status of a redirect rule, 302 unless another redirect status is set
+pub fn redirect_status(
+    status: Option<&str>,
+) -> Result<StatusCode, ConfigurationError> {
+    let invalid = || {
+        ConfigurationError::InvalidRedirectStatus(
+            status.unwrap_or_default().to_string(),
+        )
+    };
+    let status = match status {
+        Some(status) => StatusCode::from_str(status).map_err(|_| invalid())?,
+        None => StatusCode::FOUND,
+    };
+    match status.as_u16() {
+        301 | 302 | 303 | 307 | 308 => Ok(status),
+        _ => Err(invalid()),
+    }
+}
+
+impl From<&Fallback> for Then {
+    fn from(fallback: &Fallback) -> Self {
+        Then::Mock {
+            body: fallback.body.clone(),
+            body_file: None,
+            template: None,
+            content_type: None,
+            encoding: None,
+            status: fallback.status.clone(),
+            headers: fallback.headers.clone(),
+            append_headers: None,
+            set_cookies: None,
+            delete_cookies: None,
+        }
+    }
+}
@@ -1,15 +1,56 @@
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
-use super::super::configuration::Match;
+use std::collections::HashMap;
+use super::super::configuration::{
+    FileMatch, GraphQLMatch, HostMatch, JwtMatch, Match, XPathMatch,
+};
 
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct When {
     #[serde(rename = "matchesUris")]
     pub matches: Vec<Match>,
+    /// One of the entries has to match the host the request was sent to
+    #[serde(rename = "matchesHost")]
+    pub matches_host: Option<Vec<HostMatch>>,
     #[serde(rename = "matchesMethods")]
     pub matches_methods: Option<Vec<String>>,
     #[serde(rename = "bodyContains")]
     pub body_contains: Option<String>,
+    /// All xpaths have to match the xml request body
+    #[serde(rename = "matchesXPath")]
+    pub matches_xpath: Option<Vec<XPathMatch>>,
+    /// Regex for the SOAPAction header or the action of a SOAP 1.2 request
+    #[serde(rename = "matchesSoapAction")]
+    pub matches_soap_action: Option<String>,
+    #[serde(rename = "matchesGraphQL")]
+    pub matches_graphql: Option<GraphQLMatch>,
+    /// Form field names and the regex one of their values has to match
+    #[serde(rename = "matchesForm")]
+    pub matches_form: Option<HashMap<String, String>>,
+    /// Every entry has to match one of the uploaded files
+    #[serde(rename = "matchesFiles")]
+    pub matches_files: Option<Vec<FileMatch>>,
+    /// Cookie names and the regex their value has to match, an empty regex
+    /// only requires the cookie to be sent
+    #[serde(rename = "matchesCookies")]
+    pub matches_cookies: Option<HashMap<String, String>>,
+    /// The bearer token has to verify against the configured key and its
+    /// claims have to match
+    #[serde(rename = "matchesJwt")]
+    pub matches_jwt: Option<JwtMatch>,
+    /// Apply the rule to this many matching requests, later ones fall
+    /// through to the next rule
+    pub times: Option<u64>,
+    /// Let this many matching requests fall through before the rule applies
+    #[serde(rename = "afterHits")]
+    pub after_hits: Option<u64>,
+    /// Start of the time the rule applies in, a duration after the rules
+    /// were loaded (e.g. `30s`) or an RFC 3339 timestamp in UTC
+    #[serde(rename = "activeFrom")]
+    pub active_from: Option<String>,
+    /// End of the time the rule applies in, in the same format
+    #[serde(rename = "activeUntil")]
+    pub active_until: Option<String>,
 }
 
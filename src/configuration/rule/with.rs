@@ -1,11 +1,17 @@
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
-use crate::configuration::configuration::Plugin;
+use crate::configuration::configuration::{Plugin, RateLimit};
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct With {
     pub sleep: Option<u64>,
+    /// Up to this many milliseconds are added to `sleep` at random
+    pub jitter: Option<u64>,
     pub probability: Option<f32>,
     pub plugins: Option<Vec<Plugin>>,
+    /// Makes the random decisions of the rule repeatable
+    pub seed: Option<u64>,
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: Option<RateLimit>,
 }
 
@@ -8,7 +8,7 @@ pub enum RuleSet {
 }
 
 impl RuleSet {
-    pub fn into_inner(&self) -> &Rule {
+    pub fn rule(&self) -> &Rule {
         match self {
             RuleSet::Rule(rule) => rule,
         }
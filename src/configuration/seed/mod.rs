@@ -0,0 +1,47 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+pub type SharedRng = Arc<Mutex<StdRng>>;
+
+// the seed of this run
+pub fn init(seed: u64) {
+    let _ = SEED.set(seed);
+}
+
+pub fn get() -> Option<u64> {
+    SEED.get().copied()
+}
+
+// a rule's own seed wins, otherwise each rule derives one from the seed of
+// the run and its position so rules don't share a sequence
+pub fn rng(rule_seed: Option<u64>, position: usize) -> SharedRng {
+    let seed = rule_seed.or_else(|| {
+        get().map(|seed| seed.wrapping_add(position as u64))
+    });
+    let rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    Arc::new(Mutex::new(rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn draw(rng: &SharedRng) -> Vec<u32> {
+        let mut rng = rng.lock().unwrap();
+        (0..4).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn rule_seeds_repeat_their_sequence() {
+        assert_eq!(draw(&rng(Some(7), 0)), draw(&rng(Some(7), 3)));
+        assert_ne!(draw(&rng(Some(7), 0)), draw(&rng(Some(8), 0)));
+    }
+}
@@ -0,0 +1,188 @@
+use http::Uri;
+use json_dotpath::DotPaths;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use super::configuration::Match;
+use super::cookie;
+use super::intermediary::Intermediary;
+use super::jwt;
+use super::rule::Rule;
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex =
+        Regex::new(r"\{\{\s*request\.([^}\s]*)\s*\}\}").unwrap();
+}
+
+// groups of the first uri pattern matching the path, or the full url of
+// proxied requests, by index and by name
+fn captures(
+    uri: Option<&Uri>,
+    patterns: &[Match],
+) -> serde_json::Map<String, Value> {
+    let mut captures = serde_json::Map::new();
+    let Some(uri) = uri else {
+        return captures;
+    };
+    let url = uri.authority().map(|authority| {
+        let scheme = uri.scheme_str().unwrap_or("http");
+        format!("{scheme}://{authority}{}", uri.path())
+    });
+    let found = patterns.iter().find_map(|pattern| {
+        let regex = Regex::new(&pattern.uri).ok()?;
+        let found = regex
+            .captures(uri.path())
+            .or_else(|| regex.captures(url.as_deref()?))?;
+        Some((regex, found))
+    });
+
+    if let Some((regex, found)) = found {
+        for (idx, name) in regex.capture_names().enumerate() {
+            if let Some(group) = found.get(idx) {
+                let group = Value::String(group.as_str().to_string());
+                captures.insert(idx.to_string(), group.clone());
+                if let Some(name) = name {
+                    captures.insert(name.to_string(), group);
+                }
+            }
+        }
+    }
+    captures
+}
+
+// values of the incoming request that can be referred to as
+// `{{request.<dotpath>}}`, e.g. `{{request.form.fields.username}}`
+pub fn context(request: &Intermediary, rule: &Rule) -> Value {
+    let uri = request.uri.as_ref();
+    let query = uri
+        .and_then(|uri| uri.query())
+        .map(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .map(|(k, v)| (k.into_owned(), Value::String(v.into_owned())))
+                .collect::<serde_json::Map<_, _>>()
+        })
+        .unwrap_or_default();
+    // repeated headers become an array, like repeated form fields
+    let headers = request
+        .headers
+        .keys()
+        .filter_map(|name| {
+            let values = request
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(|value| Value::String(value.to_string()))
+                .collect::<Vec<_>>();
+            let value = match values.as_slice() {
+                [] => return None,
+                [single] => single.clone(),
+                _ => Value::from(values),
+            };
+            Some((name.to_string(), value))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    // a rule matching tokens only exposes claims it verified
+    let jwt = match &rule.state.jwt {
+        Some(verifier) => verifier.verify(&request.headers).ok(),
+        None => jwt::unverified_claims(&request.headers),
+    };
+    let body = match (&request.body, &request.raw_body) {
+        (Value::Null, Some(_)) => Value::String(request.body_text()),
+        (body, _) => body.clone(),
+    };
+
+    serde_json::json!({
+        "request": {
+            "method": request.method.as_ref().map(|m| m.to_string()),
+            "uri": uri.map(|uri| uri.to_string()),
+            "host": request.host(),
+            "path": uri.map(|uri| uri.path().to_string()),
+            "captures": captures(uri, &rule.when.matches),
+            "query": query,
+            "headers": headers,
+            "cookies": cookie::parse(&request.headers),
+            "jwt": jwt,
+            "body": body,
+            "form": request.form.as_ref().map(|form| form.to_value()),
+        }
+    })
+}
+
+fn lookup(context: &Value, path: &str) -> Option<Value> {
+    context
+        .dot_get::<Value>(&format!("request.{path}"))
+        .ok()
+        .flatten()
+        .filter(|value| !value.is_null())
+}
+
+// a string that only holds a placeholder is replaced by the referenced value,
+// otherwise placeholders are substituted as text. Unknown paths stay as they
+// are
+pub fn render(value: &mut Value, context: &Value) {
+    match value {
+        Value::String(s) => {
+            if let Some(captures) = PLACEHOLDER.captures(s) {
+                if captures[0].len() == s.len() {
+                    if let Some(found) = lookup(context, &captures[1]) {
+                        *value = found;
+                    }
+                    return;
+                }
+            }
+            *s = render_str(s, context);
+        }
+        Value::Array(values) => {
+            for value in values {
+                render(value, context);
+            }
+        }
+        Value::Object(values) => {
+            for (_, value) in values {
+                render(value, context);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn render_str(s: &str, context: &Value) -> String {
+    PLACEHOLDER
+        .replace_all(s, |captures: &Captures| {
+            match lookup(context, &captures[1]) {
+                Some(Value::String(found)) => found,
+                Some(found) => found.to_string(),
+                None => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::*;
+
+    #[test]
+    fn repeated_request_headers_become_a_list() {
+        let rule = Rule::from_yaml(
+            "name: t\nwhen:\n  matchesUris:\n    - uri: ^/\nthen:\n  \
+             functionAs: Mock\n",
+        )
+        .unwrap();
+        let mut request = Intermediary::request(Method::GET, "/", b"");
+        request
+            .headers
+            .append("accept", "text/html".parse().unwrap());
+        request.headers.append("accept", "*/*".parse().unwrap());
+        request.headers.append("x-id", "7".parse().unwrap());
+        let context = context(&request, &rule);
+        assert_eq!(render_str("{{request.headers.x-id}}", &context), "7");
+        let mut accept = Value::from("{{request.headers.accept}}");
+        render(&mut accept, &context);
+        assert_eq!(accept, serde_json::json!(["text/html", "*/*"]));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Filter, Native, RcIter};
+use jaq_json::Val;
+use json_patch::{Patch, PatchOperation};
+use regex::Regex;
+use serde_json::Value;
+
+use super::configuration::TextManipulation;
+use super::rule::error::ConfigurationError;
+
+// a jq filter, compiled once when the rule is loaded
+pub struct Jq(Filter<Native<Val>>);
+
+impl fmt::Debug for Jq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jq").finish_non_exhaustive()
+    }
+}
+
+impl Jq {
+    pub fn new(filter: &str) -> Result<Jq, ConfigurationError> {
+        let program = File {
+            code: filter,
+            path: (),
+        };
+
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let modules = loader.load(&arena, program).map_err(|errs| {
+            ConfigurationError::Transform(format!(
+                "could not parse filter: {errs:?}"
+            ))
+        })?;
+
+        let filter = Compiler::<_, Native<_>>::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errs| {
+                ConfigurationError::Transform(format!(
+                    "could not compile filter: {errs:?}"
+                ))
+            })?;
+        Ok(Jq(filter))
+    }
+
+    // a single output replaces the value, multiple outputs are collected
+    // into an array
+    pub fn run(&self, input: Value) -> Result<Value, ConfigurationError> {
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self
+            .0
+            .run((Ctx::new([], &inputs), Val::from(input)))
+            .map(|output| {
+                output.map(Value::from).map_err(|e| {
+                    ConfigurationError::Transform(e.to_string())
+                })
+            })
+            .collect::<Result<Vec<Value>, ConfigurationError>>()?;
+
+        Ok(match outputs.len() {
+            0 => Value::Null,
+            1 => outputs.remove(0),
+            _ => Value::Array(outputs),
+        })
+    }
+}
+
+// paths of a json patch have to be json pointers, whether they exist is
+// only known once the patch is applied
+pub fn check_patch(patch: &Patch) -> Result<(), ConfigurationError> {
+    for operation in &patch.0 {
+        let (path, from) = match operation {
+            PatchOperation::Add(op) => (&op.path, None),
+            PatchOperation::Remove(op) => (&op.path, None),
+            PatchOperation::Replace(op) => (&op.path, None),
+            PatchOperation::Move(op) => (&op.path, Some(&op.from)),
+            PatchOperation::Copy(op) => (&op.path, Some(&op.from)),
+            PatchOperation::Test(op) => (&op.path, None),
+        };
+        for pointer in std::iter::once(path).chain(from) {
+            let escapes_valid = pointer
+                .split('~')
+                .skip(1)
+                .all(|rest| rest.starts_with(['0', '1']));
+            if !(pointer.is_empty() || pointer.starts_with('/'))
+                || !escapes_valid
+            {
+                return Err(ConfigurationError::Transform(format!(
+                    "not a json pointer: {pointer}"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// regexes of the text modifications in the order they are configured,
+// compiled once when the rule is loaded
+#[derive(Debug)]
+pub struct TextPatterns(Vec<TextPattern>);
+
+#[derive(Debug)]
+struct TextPattern {
+    replace: Option<Regex>,
+    opening_tag: Option<Regex>,
+    closing_tag: Option<Regex>,
+}
+
+impl TextPatterns {
+    pub fn new(
+        manipulations: &[TextManipulation],
+    ) -> Result<TextPatterns, ConfigurationError> {
+        let patterns = manipulations
+            .iter()
+            .map(|m| {
+                let opening_tag = m.prepend_to.as_ref().map(|element| {
+                    let element = regex::escape(element);
+                    Regex::new(&format!(r"(?i)<{element}(\s[^>]*)?>"))
+                });
+                let closing_tag = m.append_to.as_ref().map(|element| {
+                    let element = regex::escape(element);
+                    Regex::new(&format!(r"(?i)</{element}\s*>"))
+                });
+                Ok(TextPattern {
+                    replace: m.replace.as_deref().map(Regex::new).transpose()?,
+                    opening_tag: opening_tag.transpose()?,
+                    closing_tag: closing_tag.transpose()?,
+                })
+            })
+            .collect::<Result<_, ConfigurationError>>()?;
+        Ok(TextPatterns(patterns))
+    }
+}
+
+pub fn text(
+    manipulations: &[TextManipulation],
+    patterns: &TextPatterns,
+    mut body: String,
+) -> String {
+    for (m, pattern) in manipulations.iter().zip(&patterns.0) {
+        if let Some(regex) = &pattern.replace {
+            body = regex.replace_all(&body, m.with.as_str()).into_owned();
+        }
+        if let Some(marker) = &m.before {
+            if let Some(idx) = body.find(marker.as_str()) {
+                body.insert_str(idx, &m.with);
+            }
+        }
+        if let Some(marker) = &m.after {
+            if let Some(idx) = body.find(marker.as_str()) {
+                body.insert_str(idx + marker.len(), &m.with);
+            }
+        }
+        if let Some(opening_tag) = &pattern.opening_tag {
+            if let Some(tag) = opening_tag.find(&body) {
+                body.insert_str(tag.end(), &m.with);
+            }
+        }
+        if let Some(closing_tag) = &pattern.closing_tag {
+            if let Some(tag) = closing_tag.find_iter(&body).last() {
+                body.insert_str(tag.start(), &m.with);
+            }
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn jq_replaces_the_value() {
+        let jq = Jq::new(".items | map(.id)").unwrap();
+        let body = json!({"items": [{"id": 1}, {"id": 2}]});
+        assert_eq!(jq.run(body).unwrap(), json!([1, 2]));
+    }
+
+    #[test]
+    fn jq_collects_several_outputs() {
+        let jq = Jq::new(".[] | .name").unwrap();
+        let body = json!([{"name": "a"}, {"name": "b"}]);
+        assert_eq!(jq.run(body).unwrap(), json!(["a", "b"]));
+        assert_eq!(
+            Jq::new("empty").unwrap().run(json!(1)).unwrap(),
+            json!(null)
+        );
+    }
+
+    #[test]
+    fn jq_errors_are_returned() {
+        assert!(Jq::new(".foo").unwrap().run(json!([1])).is_err());
+        assert!(Jq::new("error(\"x\")").unwrap().run(json!(1)).is_err());
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert!(Jq::new(".foo |").is_err());
+        assert!(Jq::new("unknown_function").is_err());
+    }
+
+    fn edit(manipulations: &str, body: &str) -> String {
+        let manipulations: Vec<TextManipulation> =
+            serde_yaml::from_str(manipulations).unwrap();
+        let patterns = TextPatterns::new(&manipulations).unwrap();
+        text(&manipulations, &patterns, body.to_string())
+    }
+
+    #[test]
+    fn replace_uses_groups() {
+        let body =
+            edit(r#"[{replace: "v(\\d+)", with: "version $1"}]"#, "v1 and v2");
+        assert_eq!(body, "version 1 and version 2");
+    }
+
+    #[test]
+    fn before_and_after_insert_at_the_first_marker() {
+        let body =
+            edit("[{before: b, with: '<'}, {after: b, with: '>'}]", "abcb");
+        assert_eq!(body, "a<b>cb");
+    }
+
+    #[test]
+    fn html_elements_get_children() {
+        let body = edit(
+            "[{prependTo: head, with: '<meta>'}, \
+             {appendTo: BODY, with: '<script></script>'}]",
+            "<html><head lang=\"en\"></head><body><p></p></body></html>",
+        );
+        assert_eq!(
+            body,
+            "<html><head lang=\"en\"><meta></head>\
+             <body><p></p><script></script></body></html>"
+        );
+    }
+
+    #[test]
+    fn invalid_replace_patterns_are_rejected() {
+        let manipulations: Vec<TextManipulation> =
+            serde_yaml::from_str("[{replace: '(', with: x}]").unwrap();
+        assert!(TextPatterns::new(&manipulations).is_err());
+    }
+
+    fn patch(operations: serde_json::Value) -> Patch {
+        serde_json::from_value(operations).unwrap()
+    }
+
+    #[test]
+    fn patch_paths_have_to_be_json_pointers() {
+        let valid = patch(json!([
+            { "op": "add", "path": "/items/-", "value": 1 },
+            { "op": "move", "from": "/a~1b", "path": "/c~0d" },
+            { "op": "replace", "path": "", "value": {} },
+        ]));
+        assert!(check_patch(&valid).is_ok());
+        let relative = patch(json!([{ "op": "remove", "path": "items" }]));
+        assert!(check_patch(&relative).is_err());
+        let from = patch(json!([{ "op": "copy", "from": "a", "path": "/b" }]));
+        assert!(check_patch(&from).is_err());
+        let escape = patch(json!([{ "op": "remove", "path": "/a~2" }]));
+        assert!(check_patch(&escape).is_err());
+    }
+}
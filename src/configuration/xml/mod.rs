@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use sxd_document::dom::{ChildOfElement, Document, Element};
+use sxd_document::{parser, writer::Writer, Package};
+use sxd_xpath::nodeset::Node;
+use sxd_xpath::{Context, Factory, Value};
+
+use super::configuration::{XPathMatch, XmlManipulation};
+use super::rule::{error::ConfigurationError, Rule};
+
+const SOAP_11_NAMESPACE: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+const SOAP_12_NAMESPACE: &str = "http://www.w3.org/2003/05/soap-envelope";
+
+fn parse(xml: &str) -> Result<Package, ConfigurationError> {
+    parser::parse(xml).map_err(|e| ConfigurationError::Xml(e.to_string()))
+}
+
+fn context<'d>(namespaces: &Option<HashMap<String, String>>) -> Context<'d> {
+    let mut context = Context::new();
+    context.set_namespace("soap", SOAP_11_NAMESPACE);
+    context.set_namespace("soap12", SOAP_12_NAMESPACE);
+    for (prefix, uri) in namespaces.iter().flatten() {
+        context.set_namespace(prefix, uri);
+    }
+    context
+}
+
+// an xpath compiled when its rule is loaded
+#[derive(Debug)]
+pub struct XPath(sxd_xpath::XPath);
+
+// SAFETY: sxd_xpath boxes its expressions as trait objects without Send and
+// Sync bounds. The expression types only own plain data that is read, never
+// changed, while evaluating
+unsafe impl Send for XPath {}
+unsafe impl Sync for XPath {}
+
+impl XPath {
+    fn new(xpath: &str) -> Result<XPath, ConfigurationError> {
+        Factory::new()
+            .build(xpath)
+            .map_err(|e| ConfigurationError::Xml(format!("{xpath}: {e}")))?
+            .map(XPath)
+            .ok_or_else(|| ConfigurationError::Xml(String::from("empty xpath")))
+    }
+
+    fn evaluate<'d>(
+        &self,
+        document: &Document<'d>,
+        namespaces: &Option<HashMap<String, String>>,
+    ) -> Result<Value<'d>, ConfigurationError> {
+        self.0
+            .evaluate(&context(namespaces), document.root())
+            .map_err(|e| ConfigurationError::Xml(e.to_string()))
+    }
+}
+
+// xpaths of `matchesXPath` with their value regex and those of the xml
+// response modifications, in the order they are configured
+#[derive(Debug)]
+pub struct XPaths {
+    matches: Vec<(XPath, Option<Regex>)>,
+    manipulations: Vec<XPath>,
+}
+
+pub fn load_rule(rule: &Rule) -> Result<Option<XPaths>, ConfigurationError> {
+    let xpath_matches = rule.when.matches_xpath.as_deref().unwrap_or_default();
+    let manipulations = rule
+        .then
+        .modify_response()
+        .and_then(|modify| modify.xml.as_deref())
+        .unwrap_or_default();
+    if xpath_matches.is_empty() && manipulations.is_empty() {
+        return Ok(None);
+    }
+
+    let matches = xpath_matches
+        .iter()
+        .map(|m| {
+            let value = m.value.as_deref().map(Regex::new).transpose()?;
+            Ok((XPath::new(&m.xpath)?, value))
+        })
+        .collect::<Result<_, ConfigurationError>>()?;
+    let manipulations = manipulations
+        .iter()
+        .map(|m| XPath::new(&m.at))
+        .collect::<Result<_, _>>()?;
+    Ok(Some(XPaths {
+        matches,
+        manipulations,
+    }))
+}
+
+// an xpath matches if its result is truthy and, if given, its string value
+// matches the `value` regex
+pub fn matches(
+    xml: &str,
+    xpath_matches: &[XPathMatch],
+    xpaths: &XPaths,
+) -> Result<bool, ConfigurationError> {
+    let package = parse(xml)?;
+    let document = package.as_document();
+
+    for (xpath_match, (xpath, value)) in
+        xpath_matches.iter().zip(&xpaths.matches)
+    {
+        let result = xpath.evaluate(&document, &xpath_match.namespaces)?;
+        let matches = match value {
+            Some(value) => value.is_match(&result.string()),
+            None => result.boolean(),
+        };
+        if !matches {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub fn modify(
+    xml: &str,
+    manipulations: &[XmlManipulation],
+    xpaths: &XPaths,
+) -> Result<String, ConfigurationError> {
+    let package = parse(xml)?;
+    let document = package.as_document();
+
+    for (m, xpath) in manipulations.iter().zip(&xpaths.manipulations) {
+        let nodes = match xpath.evaluate(&document, &m.namespaces)? {
+            Value::Nodeset(nodes) => nodes.document_order(),
+            _ => continue,
+        };
+        let fragment = m.replace.as_deref().map(parse).transpose()?;
+
+        for node in nodes {
+            if m.remove.unwrap_or(false) {
+                remove(node);
+            } else if let Some(fragment) = &fragment {
+                let fragment = fragment.as_document();
+                let replacement = fragment
+                    .root()
+                    .children()
+                    .into_iter()
+                    .find_map(|c| c.element());
+                if let (Node::Element(element), Some(replacement)) =
+                    (node, replacement)
+                {
+                    replace(element, copy(document, replacement));
+                }
+            } else if let Some(value) = &m.set {
+                set(node, value);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    Writer::new()
+        .set_single_quotes(false)
+        .format_document(&document, &mut out)?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+fn set(node: Node, value: &str) {
+    match node {
+        Node::Element(element) => {
+            element.set_text(value);
+        }
+        Node::Attribute(attribute) => {
+            if let Some(element) = attribute.parent() {
+                element.set_attribute_value(attribute.name(), value);
+            }
+        }
+        Node::Text(text) => text.set_text(value),
+        _ => {}
+    }
+}
+
+fn remove(node: Node) {
+    match node {
+        Node::Element(element) => element.remove_from_parent(),
+        Node::Attribute(attribute) => attribute.remove_from_parent(),
+        Node::Text(text) => text.remove_from_parent(),
+        Node::Comment(comment) => comment.remove_from_parent(),
+        _ => {}
+    }
+}
+
+fn replace<'d>(element: Element<'d>, replacement: Element<'d>) {
+    if let Some(parent) = element.parent() {
+        if let Some(parent) = parent.element() {
+            let children = parent.children().into_iter().map(|child| {
+                if child.element() == Some(element) {
+                    ChildOfElement::Element(replacement)
+                } else {
+                    child
+                }
+            });
+            parent.replace_children(children.collect::<Vec<_>>());
+        } else if let Some(root) = parent.root() {
+            root.clear_children();
+            root.append_child(replacement);
+        }
+    }
+}
+
+// elements can't be moved between documents, so the fragment is rebuilt
+fn copy<'d>(document: Document<'d>, source: Element) -> Element<'d> {
+    let element = document.create_element(source.name());
+    element.set_preferred_prefix(source.preferred_prefix());
+    for attribute in source.attributes() {
+        element.set_attribute_value(attribute.name(), attribute.value());
+    }
+    for child in source.children() {
+        match child {
+            ChildOfElement::Element(e) => {
+                element.append_child(copy(document, e));
+            }
+            ChildOfElement::Text(t) => {
+                element.append_child(document.create_text(t.text()));
+            }
+            ChildOfElement::Comment(c) => {
+                element.append_child(document.create_comment(c.text()));
+            }
+            ChildOfElement::ProcessingInstruction(_) => {}
+        }
+    }
+    element
+}
+
+// content type for a body that is an xml document, soap envelopes get their
+// protocol specific type
+pub fn content_type(body: &str) -> Option<&'static str> {
+    let package = parse(body.trim_start()).ok()?;
+    let document = package.as_document();
+    let root = document
+        .root()
+        .children()
+        .into_iter()
+        .find_map(|c| c.element())?;
+
+    Some(match root.name().namespace_uri() {
+        Some(SOAP_11_NAMESPACE) => "text/xml; charset=utf-8",
+        Some(SOAP_12_NAMESPACE) => "application/soap+xml; charset=utf-8",
+        _ => "application/xml",
+    })
+}
+
+// the SOAPAction header (SOAP 1.1) or the action parameter of the content
+// type (SOAP 1.2)
+pub fn soap_action(headers: &http::HeaderMap) -> Option<String> {
+    if let Some(action) = headers.get("soapaction") {
+        return action
+            .to_str()
+            .ok()
+            .map(|a| a.trim_matches('"').to_string());
+    }
+    let content_type = headers.get(http::header::CONTENT_TYPE)?.to_str().ok()?;
+    content_type.split(';').find_map(|param| {
+        param
+            .trim()
+            .strip_prefix("action=")
+            .map(|a| a.trim_matches('"').to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::intermediary::Intermediary;
+    use http::{HeaderMap, HeaderValue, Method};
+
+    const ENVELOPE: &str = concat!(
+        r#"<soap:Envelope xmlns:soap=""#,
+        r#"http://schemas.xmlsoap.org/soap/envelope/">"#,
+        "<soap:Body><GetUser><id>7</id><name>a</name></GetUser></soap:Body>",
+        "</soap:Envelope>"
+    );
+
+    fn rule(when: &str, then: &str) -> Result<Rule, ConfigurationError> {
+        Rule::from_yaml(&format!(
+            "name: xml\nwhen:\n  matchesUris:\n    - uri: ^/\n\
+             {when}then:\n{then}"
+        ))
+    }
+
+    const MOCK: &str = "  functionAs: Mock\n  body: ok\n";
+
+    fn xpath_matches(xpath: &str, value: &str) -> bool {
+        let rule = rule(
+            &format!(
+                "  matchesXPath:\n    - xpath: {xpath}\n      \
+                 value: '{value}'\n"
+            ),
+            MOCK,
+        )
+        .unwrap();
+        let xpath_matches = rule.when.matches_xpath.as_ref().unwrap();
+        matches(ENVELOPE, xpath_matches, rule.state.xpaths.as_ref().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn xpath_values_are_matched() {
+        assert!(xpath_matches("//soap:Body/GetUser/id", "^7$"));
+        assert!(!xpath_matches("//soap:Body/GetUser/id", "^8$"));
+        assert!(!xpath_matches("//missing", ".*x"));
+    }
+
+    #[test]
+    fn invalid_xpaths_are_rejected() {
+        assert!(rule("  matchesXPath:\n    - xpath: '//['\n", MOCK).is_err());
+    }
+
+    fn modified(
+        manipulations: &str,
+        xml: &str,
+    ) -> Result<String, ConfigurationError> {
+        let rule = rule(
+            "",
+            &format!(
+                "  functionAs: Fips\n  forwardUri: http://upstream\n  \
+                 modifyResponse:\n    xml:\n{manipulations}"
+            ),
+        )
+        .unwrap();
+        let manipulations =
+            rule.then.modify_response().unwrap().xml.as_ref().unwrap();
+        modify(xml, manipulations, rule.state.xpaths.as_ref().unwrap())
+    }
+
+    #[test]
+    fn nodes_are_set_replaced_and_removed() {
+        let xml = modified(
+            "      - at: //id\n        set: '8'\n\
+             \x20     - at: //name\n        remove: true\n",
+            ENVELOPE,
+        )
+        .unwrap();
+        assert!(xml.contains("<id>8</id>"));
+        assert!(!xml.contains("<name>"));
+
+        let xml = modified(
+            "      - at: //GetUser\n        replace: <Fault/>\n",
+            ENVELOPE,
+        )
+        .unwrap();
+        assert!(xml.contains("<Fault/>"));
+        assert!(!xml.contains("GetUser"));
+    }
+
+    #[test]
+    fn bodies_that_are_no_xml_are_errors() {
+        assert!(modified(
+            "      - at: //id\n        set: '8'\n",
+            r#"{"id":7}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn soap_action_is_read_from_header_or_content_type() {
+        let mut headers = HeaderMap::new();
+        headers
+            .insert("soapaction", HeaderValue::from_static("\"urn:GetUser\""));
+        assert_eq!(soap_action(&headers).as_deref(), Some("urn:GetUser"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static(
+                "application/soap+xml; action=\"urn:Get\"",
+            ),
+        );
+        assert_eq!(soap_action(&headers).as_deref(), Some("urn:Get"));
+    }
+
+    #[test]
+    fn soap_action_is_matched_by_regex() {
+        let rule = rule("  matchesSoapAction: ^urn:Get\n", MOCK).unwrap();
+        let mut request =
+            Intermediary::request(Method::POST, "/", ENVELOPE.as_bytes());
+        assert!(rule.should_apply(&request).is_err());
+        request
+            .headers
+            .insert("soapaction", HeaderValue::from_static("urn:GetUser"));
+        assert!(rule.should_apply(&request).is_ok());
+    }
+
+    #[test]
+    fn invalid_soap_action_patterns_are_rejected() {
+        assert!(rule("  matchesSoapAction: '('\n", MOCK).is_err());
+    }
+}
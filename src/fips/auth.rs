@@ -0,0 +1,146 @@
+// requests to rules with an auth block need valid credentials
+use std::str::FromStr;
+
+use bytes::Bytes;
+use eyre::Result;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE, WWW_AUTHENTICATE},
+    Response, StatusCode,
+};
+use serde_json::Value;
+
+use crate::configuration::{
+    auth::{self, Outcome},
+    holder::RuleAndIntermediaryHolder,
+    intermediary::Intermediary,
+    rule::Rule,
+    template,
+};
+
+use super::routes::ResponseBody;
+
+// the configured answer for requests without valid credentials, nothing if
+// the request is authenticated
+pub fn reject(
+    intermediary: &Intermediary,
+    rule: &Rule,
+) -> Result<Option<Response<ResponseBody>>> {
+    let Some(config) = &rule.auth else {
+        return Ok(None);
+    };
+    let outcome = auth::check(config, intermediary);
+    let answer = match outcome {
+        Outcome::Authenticated => return Ok(None),
+        Outcome::Missing => config.missing.as_ref(),
+        Outcome::Invalid => config.invalid.as_ref(),
+    };
+
+    let status = match answer.and_then(|answer| answer.status.as_ref()) {
+        Some(status) => StatusCode::from_str(status)?,
+        None => StatusCode::UNAUTHORIZED,
+    };
+    let context = template::context(intermediary, rule);
+    let body = match answer.and_then(|answer| answer.body.clone()) {
+        Some(Value::String(body)) => template::render_str(&body, &context),
+        Some(mut body) => {
+            template::render(&mut body, &context);
+            body.to_string()
+        }
+        None => match outcome {
+            Outcome::Missing => "authentication required".to_string(),
+            _ => "invalid credentials".to_string(),
+        },
+    };
+
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    if answer.is_some_and(|answer| {
+        matches!(answer.body, Some(Value::Object(_) | Value::Array(_)))
+    }) {
+        headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    }
+    // clients only look for a challenge on 401
+    if status == StatusCode::UNAUTHORIZED {
+        for challenge in auth::challenges(config, intermediary) {
+            headers
+                .append(WWW_AUTHENTICATE, HeaderValue::from_str(&challenge)?);
+        }
+    }
+    RuleAndIntermediaryHolder::set_headers(
+        headers,
+        answer.and_then(|answer| answer.headers.as_ref()),
+        false,
+        Some(&context),
+    )?;
+    Ok(Some(response.map(BodyExt::boxed_unsync)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::AUTHORIZATION, HeaderMap, Method};
+
+    use super::*;
+
+    const RULE: &str = r#"
+name: auth
+when:
+  matchesUris:
+    - uri: ^/
+auth:
+  bearer: [t0ken]
+  realm: api
+  invalid:
+    status: "403"
+    body:
+      error: forbidden
+      path: "{{request.path}}"
+    headers:
+      x-reason: refused
+then:
+  functionAs: Mock
+"#;
+
+    async fn reject_with(
+        token: Option<&str>,
+    ) -> Option<(StatusCode, HeaderMap, String)> {
+        let rule = Rule::from_yaml(RULE).unwrap();
+        let mut request = Intermediary::request(Method::GET, "/api", b"");
+        if let Some(token) = token {
+            let value = format!("Bearer {token}").parse().unwrap();
+            request.headers.insert(AUTHORIZATION, value);
+        }
+        let response = reject(&request, &rule).unwrap()?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        Some((parts.status, parts.headers, body))
+    }
+
+    #[tokio::test]
+    async fn authenticated_requests_pass() {
+        assert!(reject_with(Some("t0ken")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn missing_credentials_are_challenged() {
+        let (status, headers, body) = reject_with(None).await.unwrap();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[WWW_AUTHENTICATE], "Bearer realm=\"api\"");
+        assert_eq!(body, "authentication required");
+    }
+
+    #[tokio::test]
+    async fn invalid_credentials_get_the_configured_answer() {
+        let (status, headers, body) = reject_with(Some("other")).await.unwrap();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!headers.contains_key(WWW_AUTHENTICATE));
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+        assert_eq!(headers["x-reason"], "refused");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"], "forbidden");
+        assert_eq!(body["path"], "/api");
+    }
+}
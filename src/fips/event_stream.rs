@@ -0,0 +1,195 @@
+// server-sent events played from the rule, each event is sent once it is due
+use std::convert::Infallible;
+use std::time::Duration;
+
+use bytes::Bytes;
+use eyre::Result;
+use futures::StreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{
+    body::Frame,
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use serde_json::Value;
+
+use crate::configuration::{
+    configuration::ServerSentEvent,
+    holder::RuleAndIntermediaryHolder,
+    intermediary::Intermediary,
+    rule::{error::ConfigurationError, then::Then, Rule},
+    template,
+};
+
+use super::routes::ResponseBody;
+
+pub fn respond(
+    intermediary: &Intermediary,
+    rule: &Rule,
+) -> Result<Response<ResponseBody>> {
+    let Then::EventStream {
+        events,
+        repeat,
+        close,
+        headers,
+    } = &rule.then
+    else {
+        return Err(ConfigurationError::NotForwarding.into());
+    };
+
+    let context = template::context(intermediary, rule);
+    let events = events
+        .iter()
+        .map(|event| {
+            let mut data = event.data.clone().unwrap_or_default();
+            template::render(&mut data, &context);
+            ServerSentEvent {
+                event: event
+                    .event
+                    .as_ref()
+                    .map(|e| template::render_str(e, &context)),
+                id: event
+                    .id
+                    .as_ref()
+                    .map(|id| template::render_str(id, &context)),
+                data: Some(data),
+                ..event.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // a reconnecting client continues after the last event it received
+    let start = intermediary
+        .headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| {
+            events
+                .iter()
+                .position(|event| event.id.as_deref() == Some(id))
+        })
+        .map_or(0, |position| position + 1);
+    let total = match repeat.unwrap_or(1) {
+        0 => usize::MAX,
+        repeat => (repeat as usize * events.len()).saturating_sub(start),
+    };
+
+    let played = futures::stream::iter(
+        events.into_iter().cycle().skip(start).take(total),
+    )
+    .then(|event| async move {
+        if let Some(delay) = event.delay {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+        Ok::<_, Infallible>(Frame::data(encode(&event)))
+    });
+    let stream = if close.unwrap_or(false) {
+        played.boxed()
+    } else {
+        played.chain(futures::stream::pending()).boxed()
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(StreamBody::new(stream).boxed_unsync())?;
+    RuleAndIntermediaryHolder::set_headers(
+        response.headers_mut(),
+        headers.as_ref(),
+        false,
+        None,
+    )?;
+    Ok(response)
+}
+
+fn encode(event: &ServerSentEvent) -> Bytes {
+    let mut encoded = String::new();
+    if let Some(name) = &event.event {
+        encoded += &format!("event: {name}\n");
+    }
+    if let Some(id) = &event.id {
+        encoded += &format!("id: {id}\n");
+    }
+    if let Some(retry) = event.retry {
+        encoded += &format!("retry: {retry}\n");
+    }
+    let data = match &event.data {
+        Some(Value::String(data)) => data.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(data) => data.to_string(),
+    };
+    for line in data.split('\n') {
+        encoded += &format!("data: {line}\n");
+    }
+    encoded.push('\n');
+    Bytes::from(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+
+    const RULE: &str = r#"
+name: events
+when:
+  matchesUris:
+    - uri: ^/events
+then:
+  functionAs: EventStream
+  close: true
+  events:
+    - { event: tick, id: "1", data: "{{request.query.n}}" }
+    - { id: "2", retry: 500, data: { a: 1 } }
+"#;
+
+    fn rule(then: &str) -> Rule {
+        Rule::from_yaml(&format!("{RULE}{then}")).unwrap()
+    }
+
+    async fn play(rule: &Rule, last_event_id: Option<&str>) -> String {
+        let mut request =
+            Intermediary::request(Method::GET, "/events?n=7", b"");
+        if let Some(id) = last_event_id {
+            request.headers.insert("last-event-id", id.parse().unwrap());
+        }
+        let response = respond(&request, rule).unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    const TICK: &str = "event: tick\nid: 1\ndata: 7\n\n";
+    const SECOND: &str = "id: 2\nretry: 500\ndata: {\"a\":1}\n\n";
+
+    #[tokio::test]
+    async fn events_are_rendered_and_encoded() {
+        assert_eq!(play(&rule(""), None).await, format!("{TICK}{SECOND}"));
+    }
+
+    #[tokio::test]
+    async fn events_are_repeated() {
+        let played = play(&rule("  repeat: 2\n"), None).await;
+        assert_eq!(played, format!("{TICK}{SECOND}").repeat(2));
+    }
+
+    #[tokio::test]
+    async fn reconnecting_clients_continue_after_their_last_event() {
+        let played = play(&rule("  repeat: 2\n"), Some("1")).await;
+        assert_eq!(played, format!("{SECOND}{TICK}{SECOND}"));
+    }
+
+    #[test]
+    fn multi_line_data_gets_a_field_per_line() {
+        let event = ServerSentEvent {
+            event: None,
+            id: None,
+            retry: None,
+            delay: None,
+            data: Some(Value::from("a\nb")),
+        };
+        assert_eq!(encode(&event), "data: a\ndata: b\n\n");
+    }
+}
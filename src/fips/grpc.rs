@@ -0,0 +1,158 @@
+// grpc calls answered from json messages, the status is sent in trailers
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use eyre::Result;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{
+    body::Frame,
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use serde_json::Value;
+
+use crate::configuration::{
+    grpc,
+    holder::RuleAndIntermediaryHolder,
+    intermediary::Intermediary,
+    rule::{error::ConfigurationError, then::Then, Rule},
+    template,
+};
+
+use super::routes::ResponseBody;
+
+struct Reply {
+    messages: Vec<Bytes>,
+    status: u32,
+    message: Option<String>,
+}
+
+impl Reply {
+    fn failed(status: u32, message: String) -> Reply {
+        Reply {
+            messages: Vec::new(),
+            status,
+            message: Some(message),
+        }
+    }
+}
+
+pub fn respond(
+    intermediary: &Intermediary,
+    rule: &Rule,
+) -> Result<Response<ResponseBody>> {
+    let Then::Grpc {
+        headers, trailers, ..
+    } = &rule.then
+    else {
+        return Err(ConfigurationError::NotForwarding.into());
+    };
+
+    // failures are reported to the client as grpc status
+    let reply = answer(intermediary, rule).unwrap_or_else(|e| {
+        Reply::failed(grpc::INTERNAL, e.to_string())
+    });
+
+    let mut trailer_map = HeaderMap::new();
+    trailer_map.insert("grpc-status", HeaderValue::from(reply.status));
+    if let Some(message) = &reply.message {
+        trailer_map.insert(
+            "grpc-message",
+            HeaderValue::from_str(&grpc::encode_message(message))?,
+        );
+    }
+    RuleAndIntermediaryHolder::set_headers(
+        &mut trailer_map,
+        trailers.as_ref(),
+        false,
+        None,
+    )?;
+
+    let frames = reply
+        .messages
+        .into_iter()
+        .map(Frame::data)
+        .chain(std::iter::once(Frame::trailers(trailer_map)))
+        .map(Ok::<_, Infallible>);
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/grpc")
+        .body(StreamBody::new(futures::stream::iter(frames)).boxed_unsync())?;
+    RuleAndIntermediaryHolder::set_headers(
+        response.headers_mut(),
+        headers.as_ref(),
+        false,
+        None,
+    )?;
+    Ok(response)
+}
+
+fn answer(
+    intermediary: &Intermediary,
+    rule: &Rule,
+) -> Result<Reply, ConfigurationError> {
+    let Then::Grpc {
+        response,
+        status,
+        message,
+        ..
+    } = &rule.then
+    else {
+        return Err(ConfigurationError::NotForwarding);
+    };
+    // compiled when the rule was loaded
+    let pool = rule
+        .state
+        .descriptors
+        .as_ref()
+        .ok_or_else(|| ConfigurationError::Grpc("no descriptors".into()))?;
+
+    let path = intermediary.uri.as_ref().map_or("", |uri| uri.path());
+    let Some(method) = grpc::find_method(pool, path) else {
+        return Ok(Reply::failed(
+            grpc::UNIMPLEMENTED,
+            format!("unknown method {path}"),
+        ));
+    };
+    let requests = match grpc::decode(&method, intermediary.body_bytes()) {
+        Ok(requests) => requests,
+        Err(e) => {
+            return Ok(Reply::failed(grpc::INVALID_ARGUMENT, e.to_string()))
+        }
+    };
+
+    // templates see the request message, or all of them for client streams
+    let mut context = template::context(intermediary, rule);
+    context["request"]["body"] = if method.is_client_streaming() {
+        Value::from(requests)
+    } else {
+        requests.into_iter().next().unwrap_or_default()
+    };
+
+    let status = status.unwrap_or(0);
+    let responses = match response {
+        Some(Value::Array(items)) if method.is_server_streaming() => {
+            items.clone()
+        }
+        Some(response) => vec![response.clone()],
+        // a failing call does not need a message
+        None if status != 0 => Vec::new(),
+        None => vec![serde_json::json!({})],
+    };
+    let messages = responses
+        .into_iter()
+        .map(|mut response| {
+            template::render(&mut response, &context);
+            grpc::encode(&method, &response)
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Reply {
+        messages,
+        status,
+        message: message
+            .as_ref()
+            .map(|message| template::render_str(message, &context)),
+    })
+}
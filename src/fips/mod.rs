@@ -1,2 +1,8 @@
+pub mod auth;
+pub mod event_stream;
+pub mod grpc;
+pub mod oauth;
+pub mod proxy;
 pub mod routes;
+pub mod websocket;
 pub use routes::routes;
@@ -0,0 +1,689 @@
+// a minimal openid connect provider, codes and refresh tokens are signed
+// tokens themselves so nothing has to be kept between requests
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use eyre::Result;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    header::{
+        HeaderValue, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, HOST,
+        LOCATION, WWW_AUTHENTICATE,
+    },
+    Method, Response, StatusCode,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde_json::{json, Map, Value};
+
+use crate::configuration::{
+    auth,
+    configuration::OAuthClient,
+    intermediary::Intermediary,
+    oauth,
+    rule::{error::ConfigurationError, then::Then, Rule},
+    template,
+};
+
+use super::routes::ResponseBody;
+
+const DISCOVERY: &str = "/.well-known/openid-configuration";
+const JWKS: &str = "/jwks";
+const AUTHORIZE: &str = "/authorize";
+const TOKEN: &str = "/token";
+const USERINFO: &str = "/userinfo";
+
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+const CODE_EXPIRES_IN: u64 = 60;
+const REFRESH_EXPIRES_IN: u64 = 30 * 24 * 3600;
+const DEFAULT_SUBJECT: &str = "fips-user";
+
+// Authorization codes are not stored: a code is a signed token that can be
+// redeemed again and again until it expires, one minute after it was issued.
+// Refresh tokens can likewise be replayed until they expire
+struct Provider<'a> {
+    issuer: String,
+    clients: Option<&'a Vec<OAuthClient>>,
+    claims: Map<String, Value>,
+    expires_in: u64,
+}
+
+// answers as described in RFC 6749, errors carry `error` and
+// `error_description`
+struct OAuthError {
+    status: StatusCode,
+    error: &'static str,
+    description: String,
+}
+
+impl OAuthError {
+    fn new(error: &'static str, description: impl Into<String>) -> Self {
+        OAuthError {
+            status: StatusCode::BAD_REQUEST,
+            error,
+            description: description.into(),
+        }
+    }
+
+    fn invalid_client(description: impl Into<String>) -> Self {
+        OAuthError {
+            status: StatusCode::UNAUTHORIZED,
+            ..OAuthError::new("invalid_client", description)
+        }
+    }
+}
+
+pub fn respond(
+    intermediary: &Intermediary,
+    rule: &Rule,
+) -> Result<Response<ResponseBody>> {
+    let Then::OAuth {
+        issuer,
+        clients,
+        claims,
+        expires_in,
+    } = &rule.then
+    else {
+        return Err(ConfigurationError::NotForwarding.into());
+    };
+
+    let uri = intermediary
+        .uri
+        .as_ref()
+        .ok_or(ConfigurationError::NoUriError)?;
+    let path = uri.path().trim_end_matches('/');
+    let Some((base, endpoint)) = [DISCOVERY, JWKS, AUTHORIZE, TOKEN, USERINFO]
+        .into_iter()
+        .find_map(|endpoint| {
+            path.strip_suffix(endpoint).map(|base| (base, endpoint))
+        })
+    else {
+        return Ok(json_response(
+            StatusCode::NOT_FOUND,
+            &json!({ "error": "not_found" }),
+        ));
+    };
+
+    // the issuer is where the provider was reached unless configured
+    let issuer = issuer.clone().unwrap_or_else(|| {
+        let authority = uri
+            .authority()
+            .map(|authority| authority.to_string())
+            .or_else(|| {
+                let host = intermediary.headers.get(HOST)?;
+                host.to_str().ok().map(str::to_string)
+            })
+            .unwrap_or_else(|| "localhost".to_string());
+        format!("{}://{authority}{base}", uri.scheme_str().unwrap_or("http"))
+    });
+    let mut claims = Value::Object(claims.clone().unwrap_or_default());
+    template::render(
+        &mut claims,
+        &template::context(intermediary, rule),
+    );
+    let provider = Provider {
+        issuer: issuer.trim_end_matches('/').to_string(),
+        clients: clients.as_ref(),
+        claims: match claims {
+            Value::Object(claims) => claims,
+            _ => Map::new(),
+        },
+        expires_in: expires_in.unwrap_or(DEFAULT_EXPIRES_IN),
+    };
+
+    let answer = match endpoint {
+        DISCOVERY => Ok(json_response(StatusCode::OK, &provider.discovery())),
+        JWKS => oauth::jwks()
+            .map(|jwks| json_response(StatusCode::OK, &jwks))
+            .map_err(|e| OAuthError::new("server_error", e.to_string())),
+        AUTHORIZE => provider.authorize(&params(intermediary)),
+        TOKEN if intermediary.method != Some(Method::POST) => Err(
+            OAuthError::new("invalid_request", "tokens are requested by POST"),
+        ),
+        TOKEN => provider.token(intermediary),
+        _ => provider.userinfo(intermediary),
+    };
+    Ok(answer.unwrap_or_else(|e| {
+        let mut response = json_response(
+            e.status,
+            &json!({ "error": e.error, "error_description": e.description }),
+        );
+        if endpoint == USERINFO {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer error=\"invalid_token\""),
+            );
+        }
+        response
+    }))
+}
+
+impl Provider<'_> {
+    fn discovery(&self) -> Value {
+        let issuer = &self.issuer;
+        json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}{AUTHORIZE}"),
+            "token_endpoint": format!("{issuer}{TOKEN}"),
+            "userinfo_endpoint": format!("{issuer}{USERINFO}"),
+            "jwks_uri": format!("{issuer}{JWKS}"),
+            "response_types_supported": ["code"],
+            "grant_types_supported": [
+                "authorization_code", "client_credentials", "refresh_token"
+            ],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["ES256"],
+            "scopes_supported": ["openid"],
+            "token_endpoint_auth_methods_supported": [
+                "client_secret_basic", "client_secret_post", "none"
+            ],
+            "code_challenge_methods_supported": ["S256", "plain"],
+            "claims_supported": self.claims.keys().collect::<Vec<_>>(),
+        })
+    }
+
+    // consent is given right away, the client gets its code immediately
+    fn authorize(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<ResponseBody>, OAuthError> {
+        let client_id = required(params, "client_id")?;
+        let redirect_uri = required(params, "redirect_uri")?;
+        let client = self.client(client_id)?;
+        if client
+            .and_then(|client| client.redirect_uris.as_ref())
+            .is_some_and(|uris| !uris.iter().any(|uri| uri == redirect_uri))
+        {
+            return Err(OAuthError::new(
+                "invalid_request",
+                format!("redirect_uri {redirect_uri} is not registered"),
+            ));
+        }
+
+        let mut answer = form_urlencoded::Serializer::new(String::new());
+        if params.get("response_type").map(String::as_str) == Some("code") {
+            let code = oauth::sign(&json!({
+                "typ": "code",
+                "iss": self.issuer,
+                "aud": client_id,
+                "exp": get_current_timestamp() + CODE_EXPIRES_IN,
+                "redirect_uri": redirect_uri,
+                "scope": params.get("scope"),
+                "nonce": params.get("nonce"),
+                "code_challenge": params.get("code_challenge"),
+                "code_challenge_method": params.get("code_challenge_method"),
+            }))
+            .map_err(|e| OAuthError::new("server_error", e.to_string()))?;
+            answer.append_pair("code", &code);
+        } else {
+            answer.append_pair("error", "unsupported_response_type");
+        }
+        if let Some(state) = params.get("state") {
+            answer.append_pair("state", state);
+        }
+
+        let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+        let location = format!("{redirect_uri}{separator}{}", answer.finish());
+        let mut response = Response::new(Full::default().boxed_unsync());
+        *response.status_mut() = StatusCode::FOUND;
+        response.headers_mut().insert(
+            LOCATION,
+            HeaderValue::from_str(&location).map_err(|_| {
+                OAuthError::new("invalid_request", "invalid redirect_uri")
+            })?,
+        );
+        Ok(response)
+    }
+
+    fn token(
+        &self,
+        intermediary: &Intermediary,
+    ) -> Result<Response<ResponseBody>, OAuthError> {
+        let params = params(intermediary);
+        // clients authenticate with basic auth or in the body
+        let (client_id, secret) =
+            match auth::basic_credentials(&intermediary.headers) {
+                Some((id, secret)) => (Some(id), Some(secret)),
+                None => (
+                    params.get("client_id").cloned(),
+                    params.get("client_secret").cloned(),
+                ),
+            };
+
+        match required(&params, "grant_type")? {
+            "client_credentials" => {
+                let client_id = client_id.ok_or_else(|| {
+                    OAuthError::invalid_client("client_id is missing")
+                })?;
+                self.authenticate(&client_id, secret.as_deref(), true)?;
+                let scope = params.get("scope").cloned();
+                let claims =
+                    json!({ "sub": client_id, "client_id": client_id });
+                self.tokens(&client_id, claims, scope, None, false)
+            }
+            "authorization_code" => {
+                let code = self.redeem(required(&params, "code")?, "code")?;
+                let client_id = client_id
+                    .or_else(|| code["aud"].as_str().map(str::to_string))
+                    .unwrap_or_default();
+                if code["aud"] != client_id.as_str() {
+                    return Err(OAuthError::new(
+                        "invalid_grant",
+                        "code was issued to another client",
+                    ));
+                }
+                self.authenticate(&client_id, secret.as_deref(), false)?;
+                if params.get("redirect_uri").map(String::as_str)
+                    != code["redirect_uri"].as_str()
+                {
+                    return Err(OAuthError::new(
+                        "invalid_grant",
+                        "redirect_uri does not match",
+                    ));
+                }
+                if let Some(challenge) = code["code_challenge"].as_str() {
+                    let verified = params.get("code_verifier").is_some_and(
+                        |verifier| {
+                            oauth::pkce_matches(
+                                verifier,
+                                challenge,
+                                code["code_challenge_method"].as_str(),
+                            )
+                        },
+                    );
+                    if !verified {
+                        return Err(OAuthError::new(
+                            "invalid_grant",
+                            "code_verifier does not match",
+                        ));
+                    }
+                }
+                let scope = code["scope"].as_str().map(str::to_string);
+                self.tokens(
+                    &client_id,
+                    self.user(),
+                    scope,
+                    code["nonce"].as_str(),
+                    true,
+                )
+            }
+            "refresh_token" => {
+                let refresh = self
+                    .redeem(required(&params, "refresh_token")?, "refresh")?;
+                let client_id = client_id
+                    .or_else(|| refresh["aud"].as_str().map(str::to_string))
+                    .unwrap_or_default();
+                if refresh["aud"] != client_id.as_str() {
+                    return Err(OAuthError::new(
+                        "invalid_grant",
+                        "refresh_token was issued to another client",
+                    ));
+                }
+                self.authenticate(&client_id, secret.as_deref(), false)?;
+                let scope = refresh["scope"].as_str().map(str::to_string);
+                self.tokens(&client_id, self.user(), scope, None, true)
+            }
+            grant_type => Err(OAuthError::new(
+                "unsupported_grant_type",
+                format!("{grant_type} is not supported"),
+            )),
+        }
+    }
+
+    fn userinfo(
+        &self,
+        intermediary: &Intermediary,
+    ) -> Result<Response<ResponseBody>, OAuthError> {
+        let token = intermediary
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| OAuthError {
+                status: StatusCode::UNAUTHORIZED,
+                ..OAuthError::new("invalid_token", "bearer token is missing")
+            })?;
+        let claims = oauth::verify(token)
+            .ok()
+            .filter(|claims| claims.get("typ").is_none())
+            .ok_or_else(|| OAuthError {
+                status: StatusCode::UNAUTHORIZED,
+                ..OAuthError::new("invalid_token", "token is not valid")
+            })?;
+
+        let mut user = self.user();
+        user["sub"] = claims["sub"].clone();
+        Ok(json_response(StatusCode::OK, &user))
+    }
+
+    // access and refresh tokens, an id token for openid scopes
+    fn tokens(
+        &self,
+        client_id: &str,
+        claims: Value,
+        scope: Option<String>,
+        nonce: Option<&str>,
+        refresh: bool,
+    ) -> Result<Response<ResponseBody>, OAuthError> {
+        let now = get_current_timestamp();
+        let mut token = claims;
+        token["iss"] = json!(self.issuer);
+        token["aud"] = json!(client_id);
+        token["iat"] = json!(now);
+        token["exp"] = json!(now + self.expires_in);
+
+        let mut access = token.clone();
+        if let Some(scope) = &scope {
+            access["scope"] = json!(scope);
+        }
+        let sign = |claims: &Value| {
+            oauth::sign(claims)
+                .map_err(|e| OAuthError::new("server_error", e.to_string()))
+        };
+
+        let mut answer = json!({
+            "access_token": sign(&access)?,
+            "token_type": "Bearer",
+            "expires_in": self.expires_in,
+        });
+        if let Some(scope) = &scope {
+            answer["scope"] = json!(scope);
+        }
+        if scope
+            .as_deref()
+            .is_some_and(|scope| scope.split(' ').any(|s| s == "openid"))
+        {
+            let mut id = token.clone();
+            if let Some(nonce) = nonce {
+                id["nonce"] = json!(nonce);
+            }
+            answer["id_token"] = json!(sign(&id)?);
+        }
+        if refresh {
+            answer["refresh_token"] = json!(sign(&json!({
+                "typ": "refresh",
+                "iss": self.issuer,
+                "aud": client_id,
+                "sub": token["sub"],
+                "scope": scope,
+                "exp": now + REFRESH_EXPIRES_IN,
+            }))?);
+        }
+        Ok(json_response(StatusCode::OK, &answer))
+    }
+
+    fn user(&self) -> Value {
+        let mut user = Value::Object(self.claims.clone());
+        if user.get("sub").is_none() {
+            user["sub"] = json!(DEFAULT_SUBJECT);
+        }
+        user
+    }
+
+    // codes and refresh tokens have to be signed by fips and be of their kind
+    fn redeem(&self, token: &str, kind: &str) -> Result<Value, OAuthError> {
+        oauth::verify(token)
+            .ok()
+            .filter(|claims| claims["typ"] == kind)
+            .ok_or_else(|| {
+                OAuthError::new("invalid_grant", format!("invalid {kind}"))
+            })
+    }
+
+    fn client(
+        &self,
+        client_id: &str,
+    ) -> Result<Option<&OAuthClient>, OAuthError> {
+        match self.clients {
+            None => Ok(None),
+            Some(clients) => clients
+                .iter()
+                .find(|client| client.client_id == client_id)
+                .map(Some)
+                .ok_or_else(|| {
+                    OAuthError::invalid_client(format!(
+                        "unknown client {client_id}"
+                    ))
+                }),
+        }
+    }
+
+    fn authenticate(
+        &self,
+        client_id: &str,
+        secret: Option<&str>,
+        secret_required: bool,
+    ) -> Result<(), OAuthError> {
+        let expected = self
+            .client(client_id)?
+            .and_then(|client| client.client_secret.as_deref());
+        match (expected, secret) {
+            (Some(expected), Some(secret)) if expected != secret => {
+                Err(OAuthError::invalid_client("wrong client_secret"))
+            }
+            (Some(_), None) if secret_required => {
+                Err(OAuthError::invalid_client("client_secret is missing"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// query parameters, overridden by form fields
+fn params(intermediary: &Intermediary) -> HashMap<String, String> {
+    let mut params: HashMap<String, String> = intermediary
+        .uri
+        .as_ref()
+        .and_then(|uri| uri.query())
+        .map(|query| form_urlencoded::parse(query.as_bytes()).into_owned())
+        .into_iter()
+        .flatten()
+        .collect();
+    for (key, values) in intermediary.form.iter().flat_map(|f| &f.fields) {
+        if let Some(value) = values.first() {
+            params.insert(key.clone(), value.clone());
+        }
+    }
+    params
+}
+
+fn required<'a>(
+    params: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<&'a str, OAuthError> {
+    params.get(name).map(String::as_str).ok_or_else(|| {
+        OAuthError::new("invalid_request", format!("{name} is missing"))
+    })
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<ResponseBody> {
+    let mut response =
+        Response::new(Full::new(Bytes::from(body.to_string())).boxed_unsync());
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+name: oauth
+when:
+  matchesUris:
+    - uri: ^/auth
+then:
+  functionAs: OAuth
+  clients:
+    - clientId: app
+      clientSecret: s3cret
+      redirectUris: [http://app/cb]
+    - clientId: spa
+  claims:
+    email: ann@example.com
+"#;
+
+    // RFC 7636, appendix B
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn call(
+        method: Method,
+        uri: &str,
+        bearer: Option<&str>,
+    ) -> Response<ResponseBody> {
+        let rule = Rule::from_yaml(RULE).unwrap();
+        let mut request = Intermediary::request(method, uri, b"");
+        request.headers.insert(HOST, "idp.local".parse().unwrap());
+        if let Some(token) = bearer {
+            let value = format!("Bearer {token}").parse().unwrap();
+            request.headers.insert(AUTHORIZATION, value);
+        }
+        respond(&request, &rule).unwrap()
+    }
+
+    async fn json(response: Response<ResponseBody>) -> (StatusCode, Value) {
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn token(query: &str) -> (StatusCode, Value) {
+        json(call(Method::POST, &format!("/auth/token?{query}"), None)).await
+    }
+
+    // the code the provider redirects to http://app/cb with
+    fn authorize(query: &str) -> String {
+        let response = call(
+            Method::GET,
+            &format!(
+                "/auth/authorize?response_type=code&client_id=app&\
+                 redirect_uri=http://app/cb&state=xyz&{query}"
+            ),
+            None,
+        );
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let (uri, query) = location.split_once('?').unwrap();
+        assert_eq!(uri, "http://app/cb");
+        let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(params["state"], "xyz");
+        params["code"].clone()
+    }
+
+    #[tokio::test]
+    async fn discovery_points_to_where_the_provider_was_reached() {
+        let discovery =
+            call(Method::GET, "/auth/.well-known/openid-configuration", None);
+        let (status, discovery) = json(discovery).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(discovery["issuer"], "http://idp.local/auth");
+        assert_eq!(discovery["jwks_uri"], "http://idp.local/auth/jwks");
+        let (_, jwks) = json(call(Method::GET, "/auth/jwks", None)).await;
+        assert_eq!(jwks["keys"][0]["kty"], "EC");
+    }
+
+    #[tokio::test]
+    async fn client_credentials_need_the_client_secret() {
+        let (status, answer) =
+            token("grant_type=client_credentials&client_id=app").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(answer["error"], "invalid_client");
+        let (status, _) = token(
+            "grant_type=client_credentials&client_id=app&client_secret=no",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, answer) = token(
+            "grant_type=client_credentials&client_id=app&\
+             client_secret=s3cret&scope=read",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(answer["scope"], "read");
+        assert!(answer.get("refresh_token").is_none());
+        let access = answer["access_token"].as_str().unwrap();
+        let claims = oauth::verify(access).unwrap();
+        assert_eq!(claims["sub"], "app");
+        assert_eq!(claims["iss"], "http://idp.local/auth");
+    }
+
+    #[tokio::test]
+    async fn codes_are_exchanged_with_their_verifier() {
+        let code = authorize(&format!(
+            "scope=openid&nonce=n-1&code_challenge={CHALLENGE}&\
+             code_challenge_method=S256"
+        ));
+        let exchange = |verifier: &str| {
+            format!(
+                "grant_type=authorization_code&code={code}&\
+                 redirect_uri=http://app/cb&code_verifier={verifier}"
+            )
+        };
+        let (status, answer) = token(&exchange("wrong")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(answer["error"], "invalid_grant");
+
+        let (status, answer) = token(&exchange(VERIFIER)).await;
+        assert_eq!(status, StatusCode::OK);
+        let id = oauth::verify(answer["id_token"].as_str().unwrap()).unwrap();
+        assert_eq!(id["nonce"], "n-1");
+        assert_eq!(id["sub"], "fips-user");
+        assert_eq!(id["email"], "ann@example.com");
+
+        let access = answer["access_token"].as_str().unwrap();
+        let userinfo = call(Method::GET, "/auth/userinfo", Some(access));
+        let (status, user) = json(userinfo).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user["email"], "ann@example.com");
+
+        let refresh = answer["refresh_token"].as_str().unwrap();
+        let (status, answer) =
+            token(&format!("grant_type=refresh_token&refresh_token={refresh}"))
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(answer["access_token"].is_string());
+        // refresh tokens don't grant access
+        let userinfo = call(Method::GET, "/auth/userinfo", Some(refresh));
+        assert_eq!(userinfo.status(), StatusCode::UNAUTHORIZED);
+        assert!(userinfo.headers().contains_key(WWW_AUTHENTICATE));
+    }
+
+    #[tokio::test]
+    async fn unregistered_redirect_uris_are_rejected() {
+        let response = call(
+            Method::GET,
+            "/auth/authorize?response_type=code&client_id=app&\
+             redirect_uri=http://evil/cb",
+            None,
+        );
+        let (status, answer) = json(response).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(answer["error"], "invalid_request");
+        let unknown = call(
+            Method::GET,
+            "/auth/authorize?client_id=other&redirect_uri=http://app/cb",
+            None,
+        );
+        assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_only_issued_on_post() {
+        let response = call(
+            Method::GET,
+            "/auth/token?grant_type=client_credentials&client_id=spa",
+            None,
+        );
+        let (status, answer) = json(response).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(answer["error"], "invalid_request");
+        let other = call(Method::GET, "/auth/other", None);
+        assert_eq!(other.status(), StatusCode::NOT_FOUND);
+    }
+}
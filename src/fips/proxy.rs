@@ -0,0 +1,380 @@
+// forward proxy support: absolute-form requests, CONNECT tunnels and the
+// certificate authority signing the certificates of intercepted tunnels
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use eyre::Result;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Incoming, service::service_fn, upgrade::Upgraded, Request,
+    Response, StatusCode, Uri, Version,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use lazy_static::lazy_static;
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, IsCa, KeyPair,
+    KeyUsagePurpose,
+};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio_rustls::{
+    rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::{
+    configuration::{
+        configuration::Config,
+        intermediary::{AsyncTryFrom, Intermediary},
+    },
+    utility::log::{Loggable, LoggableType, RequestInfo, ResponseInfo},
+    PaintLogsCallbacks,
+};
+
+use super::routes::{self, ResponseBody};
+
+const CA_CERT: &str = "ca.pem";
+const CA_KEY: &str = "ca.key";
+
+lazy_static! {
+    // upstreams are reached with or without tls, trusting the system roots
+    pub static ref CLIENT: Client<HttpsConnector<HttpConnector>, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(
+            HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .unwrap_or_else(|_| {
+                    HttpsConnectorBuilder::new().with_tls_config(
+                        ClientConfig::builder()
+                            .with_root_certificates(RootCertStore::empty())
+                            .with_no_client_auth(),
+                    )
+                })
+                .https_or_http()
+                .enable_all_versions()
+                .build(),
+        );
+}
+
+// the key signs certificates for any host, only its owner may read it
+fn write_key(path: &Path, pem: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(pem.as_bytes())
+}
+
+/// Marks requests received through an intercepted tunnel
+#[derive(Clone, Copy)]
+struct Intercepted;
+
+pub struct CertificateAuthority {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl CertificateAuthority {
+    // the key is kept, clients only have to trust the certificate once
+    pub fn load_or_create(dir: &Path) -> Result<CertificateAuthority> {
+        let key_path = dir.join(CA_KEY);
+        let existing = key_path.exists();
+        let key = if existing {
+            KeyPair::from_pem(&std::fs::read_to_string(&key_path)?)?
+        } else {
+            KeyPair::generate()?
+        };
+
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "fips certificate authority");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let cert = params.self_signed(&key)?;
+
+        std::fs::create_dir_all(dir)?;
+        if !existing {
+            write_key(&key_path, &key.serialize_pem())?;
+        }
+        // a deleted certificate is written again for the kept key
+        let cert_path = dir.join(CA_CERT);
+        if !cert_path.exists() {
+            std::fs::write(cert_path, cert.pem())?;
+        }
+
+        Ok(CertificateAuthority {
+            cert,
+            key,
+            configs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // certificates are issued once per host
+    fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>> {
+        let mut configs = self.configs.lock().unwrap();
+        if let Some(config) = configs.get(host) {
+            return Ok(config.clone());
+        }
+
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![host.to_string()])?;
+        params.distinguished_name.push(DnType::CommonName, host);
+        let cert = params.signed_by(&key, &self.cert, &self.key)?;
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                    key.serialize_der(),
+                )),
+            )?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let config = Arc::new(config);
+        configs.insert(host.to_string(), config.clone());
+        Ok(config)
+    }
+}
+
+// requests meant for another host, sent by clients using fips as proxy
+pub fn is_proxied<B>(request: &Request<B>) -> bool {
+    request.extensions().get::<Intercepted>().is_some()
+        || (request.version() < Version::HTTP_2
+            && request.uri().authority().is_some())
+}
+
+pub async fn connect(
+    request: Request<Incoming>,
+    authority: Option<Arc<CertificateAuthority>>,
+    configuration: Arc<AsyncMutex<Config>>,
+    logging: Arc<PaintLogsCallbacks>,
+) -> Result<Response<ResponseBody>> {
+    let Some(target) = request.uri().authority().cloned() else {
+        let mut response =
+            Response::new(Full::new(Bytes::from("CONNECT needs host:port")));
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return Ok(response.map(BodyExt::boxed_unsync));
+    };
+
+    match authority {
+        Some(authority) => {
+            log(&logging, format!("Intercepting tunnel to {target}"));
+            let client = request.extensions().get::<SocketAddr>().copied();
+            tokio::spawn(async move {
+                let intercepted = async {
+                    let upgraded = hyper::upgrade::on(request).await?;
+                    intercept(
+                        upgraded,
+                        target.clone(),
+                        client,
+                        authority,
+                        configuration,
+                        logging.clone(),
+                    )
+                    .await
+                };
+                if let Err(e) = intercepted.await {
+                    log(&logging, format!("Tunnel to {target} failed: {e}"));
+                }
+            });
+        }
+        None => {
+            let upstream = match TcpStream::connect(target.as_str()).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    log(&logging, format!("Could not reach {target}: {e}"));
+                    let mut response = Response::new(Full::new(Bytes::from(
+                        "could not connect to upstream",
+                    )));
+                    *response.status_mut() = StatusCode::BAD_GATEWAY;
+                    return Ok(response.map(BodyExt::boxed_unsync));
+                }
+            };
+            log(&logging, format!("Tunnelling to {target}"));
+            tokio::spawn(async move {
+                let tunnelled = async {
+                    let upgraded = hyper::upgrade::on(request).await?;
+                    let mut upgraded = TokioIo::new(upgraded);
+                    let mut upstream = upstream;
+                    tokio::io::copy_bidirectional(&mut upgraded, &mut upstream)
+                        .await?;
+                    Ok::<_, eyre::Report>(())
+                };
+                if let Err(e) = tunnelled.await {
+                    log(&logging, format!("Tunnel to {target} failed: {e}"));
+                }
+            });
+        }
+    }
+
+    Ok(Response::new(Full::default().boxed_unsync()))
+}
+
+// tls is terminated with a certificate for the target, the requests inside
+// are routed like any other with their full https url
+async fn intercept(
+    upgraded: Upgraded,
+    target: hyper::http::uri::Authority,
+    client: Option<SocketAddr>,
+    authority: Arc<CertificateAuthority>,
+    configuration: Arc<AsyncMutex<Config>>,
+    logging: Arc<PaintLogsCallbacks>,
+) -> Result<()> {
+    let config = authority.server_config(target.host())?;
+    let stream = TlsAcceptor::from(config)
+        .accept(TokioIo::new(upgraded))
+        .await?;
+
+    // rules see the url as the client requested it, without the default port
+    let host = match target.port_u16() {
+        Some(443) => target.host().to_string(),
+        _ => target.to_string(),
+    };
+    let service = service_fn(move |mut request: Request<Incoming>| {
+        let configuration = configuration.clone();
+        let logging = logging.clone();
+        let host = host.clone();
+        async move {
+            let path = request
+                .uri()
+                .path_and_query()
+                .map_or("/", |path| path.as_str());
+            *request.uri_mut() =
+                Uri::from_str(&format!("https://{host}{path}"))?;
+            request.extensions_mut().insert(Intercepted);
+            if let Some(client) = client {
+                request.extensions_mut().insert(client);
+            }
+            routes::routes(request, configuration, &logging).await
+        }
+    });
+
+    auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(stream), service)
+        .await
+        .map_err(|e| eyre::eyre!(e.to_string()))
+}
+
+// requests no rule applies to go on to where they were sent
+pub async fn pass_through(
+    intermediary: &Intermediary,
+    logging: &Arc<PaintLogsCallbacks>,
+) -> Result<Response<ResponseBody>> {
+    let mut request = Request::<Full<Bytes>>::try_from(intermediary.clone())?;
+    request.headers_mut().remove("proxy-connection");
+    request.headers_mut().remove("proxy-authorization");
+
+    (logging.0)(&Loggable {
+        message_type: LoggableType::OutgoingRequestToServer(
+            RequestInfo::from(&request),
+        ),
+        message: "".to_owned(),
+    });
+    let response = match CLIENT.request(request).await {
+        Ok(response) => response,
+        Err(e) => {
+            log(logging, format!("Could not reach upstream: {e}"));
+            let mut response = Response::new(Full::new(Bytes::from(
+                "could not connect to upstream",
+            )));
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            return Ok(response.map(BodyExt::boxed_unsync));
+        }
+    };
+    (logging.0)(&Loggable {
+        message_type: LoggableType::OutGoingResponseFromFips(
+            ResponseInfo::from(&response),
+        ),
+        message: "".to_owned(),
+    });
+
+    let response = Intermediary::async_try_from(response).await?;
+    Ok(Response::from(response).map(BodyExt::boxed_unsync))
+}
+
+fn log(logging: &Arc<PaintLogsCallbacks>, message: String) {
+    (logging.0)(&Loggable {
+        message_type: LoggableType::Plain,
+        message,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(test: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("fips-proxy-{test}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn authority_is_created_and_kept() {
+        let dir = temp_dir("kept");
+        CertificateAuthority::load_or_create(&dir).unwrap();
+        let key = std::fs::read_to_string(dir.join(CA_KEY)).unwrap();
+        let cert = std::fs::read_to_string(dir.join(CA_CERT)).unwrap();
+        assert!(key.contains("PRIVATE KEY"));
+        assert!(cert.contains("CERTIFICATE"));
+
+        CertificateAuthority::load_or_create(&dir).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join(CA_KEY)).unwrap(), key);
+        assert_eq!(std::fs::read_to_string(dir.join(CA_CERT)).unwrap(), cert);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_certificate_is_written_again() {
+        let dir = temp_dir("missing");
+        CertificateAuthority::load_or_create(&dir).unwrap();
+        let key = std::fs::read_to_string(dir.join(CA_KEY)).unwrap();
+        std::fs::remove_file(dir.join(CA_CERT)).unwrap();
+
+        let authority = CertificateAuthority::load_or_create(&dir).unwrap();
+        let cert = std::fs::read_to_string(dir.join(CA_CERT)).unwrap();
+        assert_eq!(cert, authority.cert.pem());
+        assert_eq!(std::fs::read_to_string(dir.join(CA_KEY)).unwrap(), key);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn host_certificates_are_issued_once() {
+        let dir = temp_dir("hosts");
+        let authority = CertificateAuthority::load_or_create(&dir).unwrap();
+        let first = authority.server_config("example.com").unwrap();
+        let again = authority.server_config("example.com").unwrap();
+        let other = authority.server_config("example.org").unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(!Arc::ptr_eq(&first, &other));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn absolute_form_requests_are_proxied() {
+        let request = |uri| Request::get(uri).body(()).unwrap();
+        assert!(is_proxied(&request("http://example.com/users")));
+        assert!(!is_proxied(&request("/users")));
+        let mut intercepted = request("/users");
+        intercepted.extensions_mut().insert(Intercepted);
+        assert!(is_proxied(&intercepted));
+    }
+}
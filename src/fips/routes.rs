@@ -1,44 +1,39 @@
 use crate::{
     configuration::{
         configuration::Config, holder::RuleAndIntermediaryHolder,
-        intermediary::{AsyncTryFrom, Intermediary}, rule::error::ConfigurationError,
+        intermediary::{AsyncTryFrom, Intermediary},
+        rate_limit::Quota,
+        rule::{error::ConfigurationError, then::Then},
         ruleset::RuleSet,
     },
     utility::log::{Loggable, LoggableType, RequestInfo, ResponseInfo},
     PaintLogsCallbacks,
 };
 
+use super::{auth, event_stream, grpc, oauth, proxy, websocket};
+
 use bytes::Bytes;
 use hyper::{
     header::{HeaderMap, HeaderValue},
     Method, Request, Response, StatusCode,
 };
 use hyper::body::Incoming;
-use http_body_util::Full;
-use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
+use std::convert::Infallible;
 use std::sync::Arc;
 use tokio::sync::Mutex as AsyncMutex;
 
 use eyre::Result;
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum RoutingError {
-    #[error("http error")]
-    HttpHyper(#[from] hyper::Error),
-    #[error("http error")]
-    Http(#[from] http::Error),
-    #[error("Configuration Error")]
-    Configuration(#[from] ConfigurationError),
-}
+
+// mocks are sent at once, event streams piece by piece
+pub type ResponseBody = UnsyncBoxBody<Bytes, Infallible>;
 
 // this should be segmented with better care, split into smaller functions, move everything possible from state to separate arguments
 pub async fn routes(
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
     configuration: Arc<AsyncMutex<Config>>,
     logging: &Arc<PaintLogsCallbacks>,
-) -> Result<Response<Full<Bytes>>> {
+) -> Result<Response<ResponseBody>> {
     let requestinfo = RequestInfo::from(&req);
 
     let log_output = Loggable {
@@ -47,49 +42,42 @@ pub async fn routes(
     };
     (logging.0)(&log_output);
 
+    let proxied = proxy::is_proxied(&req);
+    let mut on_upgrade = websocket::is_upgrade(&req)
+        .then(|| hyper::upgrade::on(&mut req));
     let intermediary = Intermediary::async_try_from(req).await?;
 
     let c = intermediary.clone();
     //TODO clean up adding cors, have rule that makes sense here
     if let (Some(method), Some(uri)) = (&c.method, &c.uri) {
-        if method == Method::OPTIONS {
-            let mut resp = Response::new(Full::new(Bytes::new()));
+        // preflights for other hosts are answered by them
+        if method == Method::OPTIONS && !proxied {
+            let mut resp =
+                Response::new(Full::new(Bytes::new()).boxed_unsync());
             add_cors_headers(resp.headers_mut());
             return Ok(resp);
         }
         if method == Method::OPTIONS && uri == "/favicon.ico" {
             //early return for favicon
-            return Ok(Response::new(Full::default()));
+            return Ok(Response::new(Full::default().boxed_unsync()));
         }
     }
     // find first matching rule
-    let config = configuration.lock().await;
-    let matching_rule_idx =
-        config.rules.iter().enumerate().find_map(|(idx, rule)| {
-            if !config.active_rule_indices.contains(&idx) {
-                None
-            } else {
-                match rule {
-                    RuleSet::Rule(rule) => {
-                        if rule.should_apply(&intermediary).is_ok() {
-                            Some(idx)
-                        } else {
-                            None
-                        }
-                    }
-                }
-            }
-        });
-    drop(config);
+    let mut matching_rule_idx =
+        find_matching_rule(&*configuration.lock().await, &intermediary, 0);
 
-    if let Some(idx) = matching_rule_idx {
+    while let Some(idx) = matching_rule_idx {
         //add uri and route from configuration (enrich)
         let config_guard = configuration.lock().await;
         let config_clone = config_guard.clone();
 
-        let rule = config_clone.rules[idx].into_inner();
+        let rule = config_clone.rules[idx].rule();
         drop(config_guard);
-        let mut holder = RuleAndIntermediaryHolder { rule: rule.clone(), intermediary };
+        let mut holder = RuleAndIntermediaryHolder {
+            rule: rule.clone(),
+            intermediary: intermediary.clone(),
+            logging: logging.clone(),
+        };
 
         let info = Loggable {
             message_type: LoggableType::Plain,
@@ -102,10 +90,96 @@ pub async fn routes(
         };
         (logging.0)(&info);
 
-        let request = hyper::Request::try_from(&holder);
+        if let Some(mut resp) = auth::reject(&holder.intermediary, rule)? {
+            (logging.0)(&Loggable {
+                message_type: LoggableType::Plain,
+                message: format!("Rule {} requires authentication", rule.name),
+            });
+            add_cors_headers(resp.headers_mut());
+            return Ok(resp);
+        }
+
+        let quota = rule
+            .state
+            .rate_limiter
+            .as_ref()
+            .map(|limiter| limiter.check(&holder.intermediary));
+        if let Some(quota) = quota.as_ref().filter(|quota| !quota.allowed) {
+            (logging.0)(&Loggable {
+                message_type: LoggableType::Plain,
+                message: format!("Rate limit of Rule {} exceeded", rule.name),
+            });
+            let mut resp =
+                Response::new(Full::new(Bytes::from("rate limit exceeded")));
+            *resp.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            quota.apply(resp.headers_mut());
+            add_cors_headers(resp.headers_mut());
+            return Ok(resp.map(BodyExt::boxed_unsync));
+        }
+
+        if let Then::WebSocket { .. } = &rule.then {
+            let mut resp = websocket::accept(
+                &holder.intermediary,
+                on_upgrade.take(),
+                rule,
+                logging,
+            )
+            .await?;
+            add_cors_headers(resp.headers_mut());
+            add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+            return Ok(resp.map(BodyExt::boxed_unsync));
+        }
+
+        if let Then::EventStream { .. } = &rule.then {
+            let mut resp = event_stream::respond(&holder.intermediary, rule)?;
+            add_cors_headers(resp.headers_mut());
+            add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+            return Ok(resp);
+        }
+
+        if let Then::Grpc { .. } = &rule.then {
+            let mut resp = grpc::respond(&holder.intermediary, rule)?;
+            add_cors_headers(resp.headers_mut());
+            add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+            return Ok(resp);
+        }
+
+        if let Then::OAuth { .. } = &rule.then {
+            let mut resp = oauth::respond(&holder.intermediary, rule)?;
+            add_cors_headers(resp.headers_mut());
+            add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+            return Ok(resp);
+        }
+
+        // a forwarding rule whose request can't be built must not fall
+        // through to answering like a mock
+        let request = match hyper::Request::try_from(&holder) {
+            Ok(request) => Some(request),
+            Err(ConfigurationError::NotForwarding)
+                if rule.then.forward_uri().is_none() =>
+            {
+                None
+            }
+            Err(e) => {
+                (logging.0)(&Loggable {
+                    message_type: LoggableType::Plain,
+                    message: format!(
+                        "Could not build the request of Rule {}: {e}",
+                        rule.name
+                    ),
+                });
+                let mut resp = Response::new(Full::new(Bytes::from(
+                    "could not build upstream request",
+                )));
+                *resp.status_mut() = StatusCode::BAD_GATEWAY;
+                add_cors_headers(resp.headers_mut());
+                add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+                return Ok(resp.map(BodyExt::boxed_unsync));
+            }
+        };
 
         // Rule is forwarding (Proxy/FIPS)
-        let resp = if let Ok(request) = request {
+        let resp: Result<_> = if let Some(request) = request {
             let requestinfo = RequestInfo::from(&request);
             let log_output = Loggable {
                 message_type: LoggableType::OutgoingRequestToServer(
@@ -115,23 +189,63 @@ pub async fn routes(
             };
             (logging.0)(&log_output);
 
-            let client = Client::builder(TokioExecutor::new()).build_http();
-            let resp = client.request(request).await?;
+            let upstream = proxy::CLIENT.request(request).await;
 
-            let responseinfo = ResponseInfo::from(&resp);
-            let log_output = Loggable {
-                message_type: LoggableType::OutGoingResponseFromFips(
-                    responseinfo,
-                ),
-                message: "".to_owned(),
-            };
-            (logging.0)(&log_output);
+            let upstream_failed = upstream
+                .as_ref()
+                .map_or(true, |resp| resp.status().is_server_error());
 
-            let inter = Intermediary::async_try_from(resp).await?;
-            holder.intermediary = inter;
-            let mut resp = Response::async_try_from(holder).await?;
-            add_cors_headers(resp.headers_mut());
-            Ok(resp)
+            match rule.then.fallback() {
+                Some(fallback)
+                    if upstream_failed
+                        && fallback.next_rule.unwrap_or(false) =>
+                {
+                    (logging.0)(&Loggable {
+                        message_type: LoggableType::Plain,
+                        message: format!(
+                            "Upstream failed for Rule {}, trying next rule",
+                            rule.name
+                        ),
+                    });
+                    matching_rule_idx = find_matching_rule(
+                        &*configuration.lock().await,
+                        &intermediary,
+                        idx + 1,
+                    );
+                    continue;
+                }
+                Some(fallback) if upstream_failed => {
+                    (logging.0)(&Loggable {
+                        message_type: LoggableType::Plain,
+                        message: format!(
+                            "Upstream failed for Rule {}, serving fallback",
+                            rule.name
+                        ),
+                    });
+                    holder.rule.then = Then::from(fallback);
+                    let mut resp = Response::async_try_from(holder).await?;
+                    add_cors_headers(resp.headers_mut());
+                    Ok(resp)
+                }
+                _ => {
+                    let resp = upstream?;
+
+                    let responseinfo = ResponseInfo::from(&resp);
+                    let log_output = Loggable {
+                        message_type: LoggableType::OutGoingResponseFromFips(
+                            responseinfo,
+                        ),
+                        message: "".to_owned(),
+                    };
+                    (logging.0)(&log_output);
+
+                    let inter = Intermediary::async_try_from(resp).await?;
+                    holder.intermediary = inter;
+                    let mut resp = Response::async_try_from(holder).await?;
+                    add_cors_headers(resp.headers_mut());
+                    Ok(resp)
+                }
+            }
         } else {
             // rule isnt forwarding
             let mut resp = Response::async_try_from(holder).await?;
@@ -139,30 +253,64 @@ pub async fn routes(
             Ok(resp)
         };
 
-        if let Some(with) = &rule.with {
-            if let Some(sleep_time_ms) = with.sleep {
-                tokio::time::sleep(tokio::time::Duration::from_millis(
-                    sleep_time_ms,
-                ))
-                .await;
-            }
+        if let Some(delay) = rule.delay() {
+            tokio::time::sleep(delay).await;
         }
-        resp
-    } else {
-        //TODO create this from intermediary
-        let mut no_matching_rule =
-            Response::new(Full::new(Bytes::from("no matching rule found")));
-        *no_matching_rule.status_mut() = StatusCode::NOT_FOUND;
-
-        add_cors_headers(no_matching_rule.headers_mut());
-        (logging.0)(&Loggable {
-            message: format!(
-                "No matching rule found for URI: {:?}",
-                &intermediary.clone().uri
-            ),
-            message_type: LoggableType::Plain,
-        });
-        Ok(no_matching_rule)
+        let mut resp = resp?;
+        add_rate_limit_headers(resp.headers_mut(), quota.as_ref());
+        return Ok(resp);
+    }
+
+    if proxied {
+        return proxy::pass_through(&intermediary, logging).await;
+    }
+
+    //TODO create this from intermediary
+    let mut no_matching_rule =
+        Response::new(Full::new(Bytes::from("no matching rule found")));
+    *no_matching_rule.status_mut() = StatusCode::NOT_FOUND;
+
+    add_cors_headers(no_matching_rule.headers_mut());
+    (logging.0)(&Loggable {
+        message: format!(
+            "No matching rule found for URI: {:?}",
+            &intermediary.clone().uri
+        ),
+        message_type: LoggableType::Plain,
+    });
+    Ok(no_matching_rule.map(BodyExt::boxed_unsync))
+}
+
+fn find_matching_rule(
+    config: &Config,
+    intermediary: &Intermediary,
+    start: usize,
+) -> Option<usize> {
+    config
+        .rules
+        .iter()
+        .enumerate()
+        .skip(start)
+        .find_map(|(idx, rule)| {
+            if !config.active_rule_indices.contains(&idx) {
+                None
+            } else {
+                match rule {
+                    RuleSet::Rule(rule) => {
+                        if rule.should_apply(intermediary).is_ok() {
+                            Some(idx)
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+        })
+}
+
+fn add_rate_limit_headers(headers: &mut HeaderMap, quota: Option<&Quota>) {
+    if let Some(quota) = quota {
+        quota.apply(headers);
     }
 }
 
@@ -178,3 +326,177 @@ fn add_cors_headers(headers: &mut HeaderMap) {
         HeaderValue::from_static("*"),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use hyper::service::service_fn;
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::configuration::rule::Rule;
+
+    // serves these rules on a free local port, like the backend does
+    async fn serve(rules: &[String]) -> SocketAddr {
+        let rules = rules
+            .iter()
+            .map(|yaml| RuleSet::Rule(Rule::from_yaml(yaml).unwrap()))
+            .collect::<Vec<_>>();
+        let configuration = Arc::new(AsyncMutex::new(Config {
+            active_rule_indices: (0..rules.len()).collect(),
+            #[cfg(feature = "ui")]
+            fe_selected_rule: 0,
+            rules,
+        }));
+        let logging = Arc::new(PaintLogsCallbacks(Box::new(|_| {})));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, client) = listener.accept().await.unwrap();
+                let configuration = configuration.clone();
+                let logging = logging.clone();
+                let service = service_fn(move |mut req: Request<Incoming>| {
+                    req.extensions_mut().insert(client);
+                    let configuration = configuration.clone();
+                    let logging = logging.clone();
+                    async move { routes(req, configuration, &logging).await }
+                });
+                tokio::spawn(async move {
+                    let builder = auto::Builder::new(TokioExecutor::new());
+                    let io = TokioIo::new(stream);
+                    let _ = builder.serve_connection(io, service).await;
+                });
+            }
+        });
+        addr
+    }
+
+    // a port nothing listens on
+    async fn closed_port() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    fn mock(name: &str, status: u16, body: &str) -> String {
+        format!(
+            "name: {name}\nwhen:\n  matchesUris:\n    - uri: ^/\n\
+                then:\n  functionAs: Mock\n  status: \"{status}\"\n  \
+                body: {body}\n"
+        )
+    }
+
+    fn forward(upstream: SocketAddr, then: &str) -> String {
+        format!(
+            "name: forward\nwhen:\n  matchesUris:\n    - uri: ^/api\n\
+                then:\n  functionAs: Fips\n  \
+                forwardUri: http://{upstream}/\n{then}"
+        )
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> (StatusCode, String) {
+        send(Request::get(format!("http://{addr}{path}"))).await
+    }
+
+    async fn send(
+        request: hyper::http::request::Builder,
+    ) -> (StatusCode, String) {
+        let request = request.body(Full::new(Bytes::new())).unwrap();
+        let response = proxy::CLIENT.request(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    const FALLBACK: &str = "  fallback:\n    status: \"200\"\n    \
+        body: { cached: true }\n";
+
+    #[tokio::test]
+    async fn upstream_answers_pass_without_fallback() {
+        let upstream = serve(&[mock("up", 200, "live")]).await;
+        let fips = serve(&[forward(upstream, FALLBACK)]).await;
+        assert_eq!(get(fips, "/api").await, (StatusCode::OK, "live".into()));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_replaced_by_the_fallback() {
+        let upstream = serve(&[mock("up", 503, "down")]).await;
+        let fips = serve(&[forward(upstream, FALLBACK)]).await;
+        let (status, body) = get(fips, "/api").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"cached":true}"#);
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstreams_are_replaced_by_the_fallback() {
+        let fips = serve(&[forward(closed_port().await, FALLBACK)]).await;
+        let (status, body) = get(fips, "/api").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"cached":true}"#);
+    }
+
+    #[tokio::test]
+    async fn server_errors_pass_without_fallback() {
+        let upstream = serve(&[mock("up", 503, "down")]).await;
+        let fips = serve(&[forward(upstream, "")]).await;
+        assert_eq!(get(fips, "/api").await.1, "down");
+    }
+
+    #[tokio::test]
+    async fn next_rule_fallback_continues_with_the_next_rule() {
+        let next_rule = "  fallback:\n    nextRule: true\n";
+        let fips = serve(&[
+            forward(closed_port().await, next_rule),
+            mock("next", 200, "from next rule"),
+        ])
+        .await;
+        let (status, body) = get(fips, "/api").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "from next rule"));
+    }
+
+    #[tokio::test]
+    async fn unbuildable_requests_are_answered_with_bad_gateway() {
+        let upstream = serve(&[mock("up", 200, "live")]).await;
+        let modify = "  modifyRequest:\n    method: \"GE T\"\n";
+        let fips = serve(&[forward(upstream, modify)]).await;
+        let (status, body) = get(fips, "/api").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body, "could not build upstream request");
+    }
+
+    #[tokio::test]
+    async fn exhausted_quotas_are_answered_with_too_many_requests() {
+        let limited = format!(
+            "{}with:\n  rateLimit:\n    limit: 1\n    window: 60000\n",
+            mock("limited", 200, "ok")
+        );
+        let fips = serve(&[limited]).await;
+        assert_eq!(get(fips, "/").await.0, StatusCode::OK);
+        let uri = format!("http://{fips}/");
+        let request = Request::get(uri).body(Full::new(Bytes::new())).unwrap();
+        let response = proxy::CLIENT.request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn protected_rules_need_credentials() {
+        let protected =
+            format!("{}auth:\n  bearer: [t0ken]\n", mock("api", 200, "ok"));
+        let fips = serve(&[protected]).await;
+        let (status, body) = get(fips, "/").await;
+        assert_eq!(
+            (status, body.as_str()),
+            (StatusCode::UNAUTHORIZED, "authentication required")
+        );
+        let authorized = Request::get(format!("http://{fips}/"))
+            .header("authorization", "Bearer t0ken");
+        assert_eq!(send(authorized).await, (StatusCode::OK, "ok".into()));
+    }
+}
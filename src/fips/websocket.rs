@@ -0,0 +1,475 @@
+// websocket upgrades, either played from the rule's script or tunnelled to
+// an upstream websocket
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use eyre::Result;
+use futures::{SinkExt, StreamExt};
+use http_body_util::Full;
+use hyper::{
+    header::{
+        HeaderValue, CONNECTION, HOST, SEC_WEBSOCKET_ACCEPT,
+        SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL,
+        SEC_WEBSOCKET_VERSION, UPGRADE,
+    },
+    upgrade::{OnUpgrade, Upgraded},
+    Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use regex::Regex;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    configuration::{
+        configuration::{WebSocketClose, WebSocketPush, WebSocketReply},
+        intermediary::Intermediary,
+        rule::{error::ConfigurationError, then::Then, Rule},
+        template,
+    },
+    utility::log::{Loggable, LoggableType, WebSocketInfo},
+    PaintLogsCallbacks,
+};
+
+const CLOSE_GRACE_MS: u64 = 1000;
+
+type ClientSocket = WebSocketStream<TokioIo<Upgraded>>;
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+struct Script {
+    on_connect: Vec<Message>,
+    replies: Vec<(Regex, WebSocketReply)>,
+    pushes: Vec<(WebSocketPush, Message)>,
+    close: Option<WebSocketClose>,
+    context: Value,
+}
+
+pub fn is_upgrade<B>(request: &Request<B>) -> bool {
+    let has_token = |header, token: &str| {
+        request.headers().get_all(header).iter().any(|value| {
+            value.to_str().is_ok_and(|value| {
+                value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token))
+            })
+        })
+    };
+    has_token(CONNECTION, "upgrade") && has_token(UPGRADE, "websocket")
+}
+
+// answers the handshake and hands the connection to a background task
+pub async fn accept(
+    intermediary: &Intermediary,
+    on_upgrade: Option<OnUpgrade>,
+    rule: &Rule,
+    logging: &Arc<PaintLogsCallbacks>,
+) -> Result<Response<Full<Bytes>>> {
+    let (Some(on_upgrade), Some(key)) =
+        (on_upgrade, intermediary.headers.get(SEC_WEBSOCKET_KEY))
+    else {
+        let mut response =
+            Response::new(Full::new(Bytes::from("websocket upgrade required")));
+        *response.status_mut() = StatusCode::UPGRADE_REQUIRED;
+        return Ok(response);
+    };
+
+    let Then::WebSocket {
+        on_connect,
+        replies,
+        pushes,
+        close,
+        forward_uri,
+    } = &rule.then
+    else {
+        return Err(ConfigurationError::NotForwarding.into());
+    };
+
+    let uri = intermediary
+        .uri
+        .as_ref()
+        .map(|uri| uri.to_string())
+        .unwrap_or_default();
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(key.as_bytes()));
+
+    if let Some(forward_uri) = forward_uri {
+        let upstream = connect_upstream(intermediary, forward_uri).await;
+        let (upstream, protocol) = match upstream {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                log(logging, LoggableType::Plain, &uri, e.to_string());
+                let mut response = Response::new(Full::new(Bytes::from(
+                    "could not connect to upstream websocket",
+                )));
+                *response.status_mut() = StatusCode::BAD_GATEWAY;
+                return Ok(response);
+            }
+        };
+        if let Some(protocol) = protocol {
+            response = response.header(SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+
+        let logging = logging.clone();
+        tokio::spawn(async move {
+            if let Some(client) = upgrade(on_upgrade).await {
+                tunnel(client, upstream, &uri, &logging).await;
+            }
+        });
+    } else {
+        // clients fail the handshake if none of their protocols is chosen
+        if let Some(protocol) = intermediary
+            .headers
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+        {
+            response = response.header(SEC_WEBSOCKET_PROTOCOL, protocol.trim());
+        }
+
+        let context = template::context(intermediary, rule);
+        let script = Script {
+            on_connect: on_connect
+                .iter()
+                .flatten()
+                .map(|value| to_message(value, &context))
+                .collect(),
+            // the patterns were compiled when the rule was loaded
+            replies: rule
+                .state
+                .replies
+                .iter()
+                .flat_map(|regexes| regexes.iter().cloned())
+                .zip(replies.iter().flatten().cloned())
+                .collect(),
+            pushes: pushes
+                .iter()
+                .flatten()
+                .map(|push| (push.clone(), to_message(&push.send, &context)))
+                .collect(),
+            close: close.clone(),
+            context,
+        };
+
+        let logging = logging.clone();
+        tokio::spawn(async move {
+            if let Some(client) = upgrade(on_upgrade).await {
+                play(client, script, &uri, &logging).await;
+            }
+        });
+    }
+
+    Ok(response.body(Full::new(Bytes::new()))?)
+}
+
+async fn upgrade(on_upgrade: OnUpgrade) -> Option<ClientSocket> {
+    match on_upgrade.await {
+        Ok(upgraded) => Some(
+            WebSocketStream::from_raw_socket(
+                TokioIo::new(upgraded),
+                Role::Server,
+                None,
+            )
+            .await,
+        ),
+        Err(e) => {
+            log::info!("websocket upgrade failed: {e}");
+            None
+        }
+    }
+}
+
+async fn connect_upstream(
+    intermediary: &Intermediary,
+    forward_uri: &str,
+) -> Result<(UpstreamSocket, Option<HeaderValue>)> {
+    let mut request = forward_uri.into_client_request()?;
+
+    // the handshake headers are generated for the upstream connection
+    for (key, value) in intermediary.headers.iter() {
+        let is_handshake = [
+            HOST,
+            CONNECTION,
+            UPGRADE,
+            SEC_WEBSOCKET_KEY,
+            SEC_WEBSOCKET_VERSION,
+            SEC_WEBSOCKET_EXTENSIONS,
+        ]
+        .contains(key);
+        if !is_handshake {
+            request.headers_mut().append(key, value.clone());
+        }
+    }
+
+    let (upstream, response) =
+        tokio_tungstenite::connect_async(request).await?;
+    let protocol = response.headers().get(SEC_WEBSOCKET_PROTOCOL).cloned();
+    Ok((upstream, protocol))
+}
+
+async fn tunnel(
+    client: ClientSocket,
+    upstream: UpstreamSocket,
+    uri: &str,
+    logging: &Arc<PaintLogsCallbacks>,
+) {
+    let (mut client_sink, mut client_stream) = client.split();
+    let (mut upstream_sink, mut upstream_stream) = upstream.split();
+
+    // close frames are passed on like any other message, reading on after
+    // them completes the closing handshake
+    let to_upstream = async {
+        while let Some(Ok(message)) = client_stream.next().await {
+            log_message(logging, true, uri, &message);
+            if upstream_sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    };
+    let to_client = async {
+        while let Some(Ok(message)) = upstream_stream.next().await {
+            log_message(logging, false, uri, &message);
+            if client_sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::pin!(to_upstream, to_client);
+
+    // once one side is gone, the other gets a moment to finish
+    let grace = Duration::from_millis(CLOSE_GRACE_MS);
+    tokio::select! {
+        _ = &mut to_upstream => {
+            let _ = tokio::time::timeout(grace, to_client).await;
+        }
+        _ = &mut to_client => {
+            let _ = tokio::time::timeout(grace, to_upstream).await;
+        }
+    }
+}
+
+async fn play(
+    client: ClientSocket,
+    script: Script,
+    uri: &str,
+    logging: &Arc<PaintLogsCallbacks>,
+) {
+    let (mut sink, mut stream) = client.split();
+    let (push_tx, mut push_rx) = mpsc::unbounded_channel();
+
+    // push tasks end once the receiver is dropped with the connection
+    for (push, message) in script.pushes {
+        let push_tx = push_tx.clone();
+        tokio::spawn(async move {
+            let every = Duration::from_millis(push.every.max(1));
+            let mut interval = tokio::time::interval(every);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if push_tx.send(message.clone()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let close_after = script.close.as_ref().and_then(|close| close.after);
+    let close_timer = async {
+        match close_after {
+            Some(after) => {
+                tokio::time::sleep(Duration::from_millis(after)).await
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(close_timer);
+
+    for message in script.on_connect {
+        log_message(logging, false, uri, &message);
+        if sink.send(message).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let mut outgoing = Vec::new();
+        let mut should_close = false;
+
+        tokio::select! {
+            incoming = stream.next() => match incoming {
+                Some(Ok(message)) => {
+                    log_message(logging, true, uri, &message);
+                    if message.is_close() {
+                        break;
+                    }
+                    if let Message::Text(text) = &message {
+                        for (regex, reply) in &script.replies {
+                            if regex.is_match(text) {
+                                outgoing.extend(
+                                    reply.send.iter().flatten().map(|value| {
+                                        to_message(value, &script.context)
+                                    }),
+                                );
+                                should_close |= reply.close.unwrap_or(false);
+                            }
+                        }
+                    }
+                }
+                _ => break,
+            },
+            Some(message) = push_rx.recv() => outgoing.push(message),
+            _ = &mut close_timer => should_close = true,
+        }
+
+        for message in outgoing {
+            log_message(logging, false, uri, &message);
+            if sink.send(message).await.is_err() {
+                return;
+            }
+        }
+        if should_close {
+            let close = script.close.as_ref();
+            let message = Message::Close(Some(CloseFrame {
+                code: CloseCode::from(
+                    close.and_then(|close| close.code).unwrap_or(1000),
+                ),
+                reason: close
+                    .and_then(|close| close.reason.clone())
+                    .unwrap_or_default()
+                    .into(),
+            }));
+            log_message(logging, false, uri, &message);
+            let _ = sink.send(message).await;
+            break;
+        }
+    }
+}
+
+// strings are sent as they are, other values as json text
+fn to_message(value: &Value, context: &Value) -> Message {
+    let mut value = value.clone();
+    template::render(&mut value, context);
+    match value {
+        Value::String(text) => Message::Text(text),
+        value => Message::Text(value.to_string()),
+    }
+}
+
+fn log_message(
+    logging: &Arc<PaintLogsCallbacks>,
+    incoming: bool,
+    uri: &str,
+    message: &Message,
+) {
+    let text = match message {
+        Message::Text(text) => text.clone(),
+        Message::Binary(data) => format!("<{} bytes>", data.len()),
+        Message::Close(Some(frame)) => {
+            format!("close {} {}", u16::from(frame.code), frame.reason)
+        }
+        Message::Close(None) => String::from("close"),
+        _ => return,
+    };
+    let info = WebSocketInfo {
+        uri: uri.to_string(),
+        message: text.clone(),
+    };
+    let message_type = if incoming {
+        LoggableType::IncomingWebSocketMessage(info)
+    } else {
+        LoggableType::OutgoingWebSocketMessage(info)
+    };
+    log(logging, message_type, uri, text);
+}
+
+fn log(
+    logging: &Arc<PaintLogsCallbacks>,
+    message_type: LoggableType,
+    uri: &str,
+    message: String,
+) {
+    (logging.0)(&Loggable {
+        message_type,
+        message: format!("WebSocket {uri}: {message}"),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+
+    const SCRIPTED: &str = r#"
+name: socket
+when:
+  matchesUris:
+    - uri: ^/ws
+then:
+  functionAs: WebSocket
+  replies:
+    - matches: ^ping$
+      send: [pong]
+    - matches: ^echo
+      send: [echo]
+"#;
+
+    #[test]
+    fn upgrade_needs_both_headers() {
+        let request = |connection, upgrade| {
+            Request::builder()
+                .header(CONNECTION, connection)
+                .header(UPGRADE, upgrade)
+                .body(())
+                .unwrap()
+        };
+        assert!(is_upgrade(&request("keep-alive, Upgrade", "websocket")));
+        assert!(!is_upgrade(&request("keep-alive", "websocket")));
+        assert!(!is_upgrade(&request("upgrade", "h2c")));
+    }
+
+    #[test]
+    fn reply_patterns_are_compiled_with_the_rule() {
+        let rule = Rule::from_yaml(SCRIPTED).unwrap();
+        let replies = rule.state.replies.unwrap();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].is_match("ping"));
+        assert!(!replies[0].is_match("pings"));
+    }
+
+    #[test]
+    fn invalid_reply_pattern_fails_the_rule() {
+        let yaml = SCRIPTED.replace("^ping$", "(ping");
+        assert!(Rule::from_yaml(&yaml).is_err());
+    }
+
+    #[test]
+    fn messages_are_rendered_from_the_request() {
+        let rule = Rule::from_yaml(SCRIPTED).unwrap();
+        let request = Intermediary::request(Method::GET, "/ws?room=1", b"");
+        let context = template::context(&request, &rule);
+        let text = to_message(&Value::from("{{request.path}}"), &context);
+        assert_eq!(text, Message::Text("/ws".into()));
+        let json = to_message(&serde_json::json!({"n": 1}), &context);
+        assert_eq!(json, Message::Text(r#"{"n":1}"#.into()));
+    }
+
+    #[tokio::test]
+    async fn requests_without_upgrade_are_refused() {
+        let rule = Rule::from_yaml(SCRIPTED).unwrap();
+        let request = Intermediary::request(Method::GET, "/ws", b"");
+        let logging = Arc::new(PaintLogsCallbacks(Box::new(|_| {})));
+        let response = accept(&request, None, &rule, &logging).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    }
+}
@@ -45,20 +45,9 @@ use crate::{
 use eyre::{ContextCompat, Result};
 use std::panic;
 use std::sync::{Arc, Mutex};
-use thiserror::Error;
 use tokio::runtime::Runtime;
 use tokio::sync::Mutex as AsyncMutex;
 
-#[derive(Error, Debug)]
-pub enum FrontendError {
-    #[error("Failed to start frontend due to io error")]
-    GenericFrontend(#[from] std::io::Error),
-    #[error("Failed to start frontend due to channel error")]
-    Input(#[from] std::sync::mpsc::RecvError),
-    #[error("unexpected none option")]
-    NoneOption,
-}
-
 pub async fn spawn_frontend(
     _app: Option<App<'_>>,
     runtime: Runtime,
@@ -103,8 +92,7 @@ pub async fn spawn_frontend(
         Box::new(move |panic_info| {
             log::error!("Panic: {}", panic_info);
             captured_state
-                .add_message(PrintInfo::Plain(panic_info.to_string()))
-                .unwrap();
+                .add_message(PrintInfo::Plain(panic_info.to_string()));
         })
     });
 
@@ -116,7 +104,7 @@ pub async fn spawn_frontend(
             .await
             .rules
             .iter()
-            .map(|x| x.into_inner())
+            .map(|x| x.rule())
             .filter_map(|rule| {
                 rule.plugins.as_ref().map(|plugins| {
                     let functions = plugins.functions.lock().unwrap();
@@ -174,34 +162,35 @@ pub fn define_log_callbacks(state: Arc<State>) -> PaintLogsCallbacks {
             inner_state
                 .add_traffic_info(LoggableNT(
                     LoggableType::IncomingRequestAtFfips(i.clone()),
-                ))
-                .unwrap();
+                ));
         }
         LoggableType::OutGoingResponseFromFips(i) => {
             inner_state
                 .add_traffic_info(LoggableNT(
                     LoggableType::OutGoingResponseFromFips(i.clone()),
-                ))
-                .unwrap();
+                ));
         }
         LoggableType::OutgoingRequestToServer(i) => {
             inner_state
                 .add_traffic_info(LoggableNT(
                     LoggableType::OutgoingRequestToServer(i.clone()),
-                ))
-                .unwrap();
+                ));
+        }
+        LoggableType::IncomingWebSocketMessage(i) => {
+            inner_state
+                .add_traffic_info(LoggableNT(
+                    LoggableType::IncomingWebSocketMessage(i.clone()),
+                ));
         }
-        LoggableType::IncomingResponseFromServer(i) => {
+        LoggableType::OutgoingWebSocketMessage(i) => {
             inner_state
                 .add_traffic_info(LoggableNT(
-                    LoggableType::IncomingResponseFromServer(i.clone()),
-                ))
-                .unwrap();
+                    LoggableType::OutgoingWebSocketMessage(i.clone()),
+                ));
         }
         LoggableType::Plain => {
             inner_state
-                .add_message(PrintInfo::Plain(message.message.clone()))
-                .unwrap();
+                .add_message(PrintInfo::Plain(message.message.clone()));
         }
     });
     PaintLogsCallbacks(log)
@@ -221,7 +210,7 @@ pub async fn setup(
         traffic_info: Mutex::new(vec![]),
     });
 
-    let app = App::new(true, Arc::clone(&state), options);
+    let app = App::new(Arc::clone(&state), options);
 
     let logging = Arc::new(define_log_callbacks(Arc::clone(&state)));
     (Some(state), Some(app), logging)
@@ -12,12 +12,16 @@ use tokio::runtime::Runtime;
 mod backend;
 mod configuration;
 mod fips;
-mod plugin_registry;
+#[cfg(feature = "ui")]
 mod terminal_ui;
 mod utility;
 
+// plugins link against the same registry through the library
+use ::fips::plugin_registry;
+
 use crate::configuration::ruleset::RuleSet;
-use crate::utility::log::Loggable;
+use crate::fips::proxy::CertificateAuthority;
+use crate::utility::log::{Loggable, LoggableType};
 use crate::utility::options::CliOptions;
 
 use tokio::sync::Mutex as AsyncMutex;
@@ -32,8 +36,21 @@ type LogFunction = Box<dyn Fn(&Loggable) + Send + Sync>;
 
 pub struct PaintLogsCallbacks(LogFunction);
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli_options = CliOptions::parse();
+
+    // plugins read the seed from the environment, it is set before the
+    // runtime starts any threads
+    let seed = cli_options.seed.unwrap_or_else(rand::random);
+    std::env::set_var("FIPS_SEED", seed.to_string());
+
+    Runtime::new()?.block_on(run(cli_options, seed))
+}
+
+async fn run(
+    cli_options: CliOptions,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(feature = "logging")]
     {
         logging::init()?;
@@ -45,8 +62,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
-    let cli_options = CliOptions::parse();
-
     if cli_options.write_schema {
         let schema = schemars::schema_for!(Vec<RuleSet>);
         serde_json::to_writer(&File::create("fips-schema.json")?, &schema)?;
@@ -54,10 +69,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     };
 
+    // rules are seeded while loading
+    configuration::seed::init(seed);
+
     //TODO: get rid of duplication caused by introduction of async mutex
-    let async_configuration = Arc::new(AsyncMutex::new(
-        Config::load(&cli_options.config).unwrap_or_default(),
-    ));
+    let (configuration, load_error) = match Config::load(&cli_options.config)
+    {
+        Ok(configuration) => (configuration, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    let async_configuration = Arc::new(AsyncMutex::new(configuration));
 
     let (_state, _app, logging) = {
         #[cfg(feature = "ui")]
@@ -73,12 +94,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         (state, app, logging)
     };
 
+    (logging.0)(&Loggable {
+        message_type: LoggableType::Plain,
+        message: format!("Using seed {seed}, replay with --seed {seed}"),
+    });
+    if let Some(e) = &load_error {
+        #[cfg(not(feature = "ui"))]
+        eprintln!("Could not load configuration: {e}");
+        (logging.0)(&Loggable {
+            message_type: LoggableType::Plain,
+            message: format!("Could not load configuration: {e}"),
+        });
+    }
+
     let addr = ([127, 0, 0, 1], cli_options.port).into();
     let runtime = Runtime::new().unwrap();
     let _guard = runtime.enter();
 
-    let _rt_handle =
-        backend::spawn_backend(&async_configuration, &addr, &logging);
+    let authority = if cli_options.mitm {
+        Some(Arc::new(CertificateAuthority::load_or_create(
+            &cli_options.ca_dir,
+        )?))
+    } else {
+        None
+    };
+
+    let _rt_handle = backend::spawn_backend(
+        &async_configuration,
+        &addr,
+        &logging,
+        authority,
+    );
 
     #[cfg(feature = "ui")]
     {
@@ -87,7 +133,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     #[cfg(not(feature = "ui"))]
     {
-        println!("server is running");
+        println!("server is running with seed {seed}");
         _rt_handle.await?.unwrap();
     }
 
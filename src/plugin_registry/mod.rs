@@ -23,6 +23,8 @@ pub enum InvocationError {
 pub struct PluginDeclaration {
     pub rustc_version: &'static str,
     pub core_version: &'static str,
+    // plugins are rust libraries, the version checks above guard the ABI
+    #[allow(improper_ctypes_definitions)]
     pub register: unsafe extern "C" fn(&mut dyn PluginRegistrar),
 }
 
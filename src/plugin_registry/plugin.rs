@@ -78,7 +78,7 @@ impl ExternalFunctions {
         if decl.rustc_version != RUSTC_VERSION
             || decl.core_version != CORE_VERSION
         {
-            return Err(io::Error::new(io::ErrorKind::Other, "Version mismatch"));
+            return Err(io::Error::other("Version mismatch"));
         }
 
         let mut registrar = PluginRegistrar::new(Arc::clone(&library));
@@ -94,7 +94,7 @@ impl ExternalFunctions {
     }
 
     pub fn load_from_file(&mut self, plugin_path: &PathBuf) -> io::Result<()> {
-        #[cfg(feature = "enablelog")]
+        #[cfg(feature = "logging")]
         log::info!("Loading plugin from {:?}", plugin_path);
 
         unsafe {
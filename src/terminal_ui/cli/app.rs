@@ -14,8 +14,6 @@ const PLUGINS_TAB_NAME: &str = "Loaded Plugins";
 pub struct App<'a> {
     pub should_quit: bool,
     pub tabs: TabsState<'a>,
-    pub show_chart: bool,
-    pub enhanced_graphics: bool,
     pub state: Arc<State>,
     pub opts: CliOptions,
     pub glow_interval: u8,
@@ -30,7 +28,7 @@ enum Dir {
 }
 
 impl<'a> App<'a> {
-    pub fn new(enhanced_graphics: bool, state: Arc<State>, opts: CliOptions) -> App<'a> {
+    pub fn new(state: Arc<State>, opts: CliOptions) -> App<'a> {
         App {
             should_quit: false,
             tabs: TabsState::new(vec![
@@ -39,8 +37,6 @@ impl<'a> App<'a> {
                 RULES_TAB_NAME,
                 PLUGINS_TAB_NAME,
             ]),
-            show_chart: true,
-            enhanced_graphics,
             state,
             opts,
             glow_interval: 0,
@@ -30,7 +30,7 @@ impl<'a> AsyncFrom<ConfigurationNewtype> for List<'a> {
                 let mut lines: Vec<Spans> = vec![];
                 lines.extend(vec![Spans::from(format!(
                     "name: {} --- path: {}",
-                    r.into_inner().name, r.into_inner().path
+                    r.rule().name, r.rule().path
                 ))]);
                 // can be used to set background color of a rule
                 let bg = match true {
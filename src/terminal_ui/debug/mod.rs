@@ -1,12 +1,8 @@
 pub mod print_info;
 
-mod fips_info;
 mod request_info;
 mod response_info;
 mod traffic_info;
 
-pub use fips_info::FipsInfo;
 pub use print_info::PrintInfo;
-pub use request_info::RequestInfoNT;
-pub use response_info::ResponseInfoNT;
 pub use traffic_info::LoggableNT;
@@ -11,15 +11,14 @@ impl<B> From<&Request<B>> for RequestInfoNT {
         let method = String::from(request.method().as_str());
         let uri = request.uri().to_string();
         let version = format!("{:?}", request.version());
-        let mut headers = HashMap::new();
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
         for (k, v) in request.headers() {
-            headers.insert(
-                String::from(k.as_str()),
-                String::from(v.to_str().unwrap()),
-            );
+            headers
+                .entry(String::from(k.as_str()))
+                .or_default()
+                .push(String::from_utf8_lossy(v.as_bytes()).into_owned());
         }
         RequestInfoNT(RequestInfo {
-            request_type: String::from("placeholder"),
             method,
             uri,
             version,
@@ -35,11 +34,13 @@ impl<'a> From<&RequestInfoNT> for Text<'a> {
         text.push_str(&request_info.0.uri);
         text.push(' ');
         text.push_str(&request_info.0.version);
-        for (k, v) in &request_info.0.headers {
-            text += "\n";
-            text += k;
-            text += " : ";
-            text += v;
+        for (k, values) in &request_info.0.headers {
+            for v in values {
+                text += "\n";
+                text += k;
+                text += " : ";
+                text += v;
+            }
         }
         Text::from(text)
     }
@@ -10,15 +10,14 @@ impl<B> From<&Response<B>> for ResponseInfoNT {
     fn from(response: &Response<B>) -> ResponseInfoNT {
         let status = String::from(response.status().as_str());
         let version = format!("{:?}", response.version());
-        let mut headers = HashMap::new();
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
         for (k, v) in response.headers() {
-            headers.insert(
-                String::from(k.as_str()),
-                String::from(v.to_str().unwrap()),
-            );
+            headers
+                .entry(String::from(k.as_str()))
+                .or_default()
+                .push(String::from_utf8_lossy(v.as_bytes()).into_owned());
         }
         ResponseInfoNT(ResponseInfo {
-            response_type: String::from("placeholder"),
             status,
             version,
             headers,
@@ -31,11 +30,13 @@ impl<'a> From<&ResponseInfoNT> for Text<'a> {
         let mut text = String::from(&request_info.0.status);
         text.push(' ');
         text.push_str(&request_info.0.version);
-        for (k, v) in &request_info.0.headers {
-            text += "\n";
-            text += k;
-            text += " : ";
-            text += v;
+        for (k, values) in &request_info.0.headers {
+            for v in values {
+                text += "\n";
+                text += k;
+                text += " : ";
+                text += v;
+            }
         }
         Text::from(text)
     }
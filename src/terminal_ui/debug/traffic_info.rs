@@ -11,10 +11,15 @@ pub struct LoggableNT(pub LoggableType);
 impl fmt::Display for LoggableNT {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let info_string = match &self.0 {
-            LoggableType::IncomingResponseFromServer(_) => "Incoming Response",
             LoggableType::OutGoingResponseFromFips(_) => "Outgoing Response",
             LoggableType::OutgoingRequestToServer(_) => "Outgoing Request",
             LoggableType::IncomingRequestAtFfips(_) => "Incoming Request",
+            LoggableType::IncomingWebSocketMessage(_) => {
+                "Incoming WebSocket Message"
+            }
+            LoggableType::OutgoingWebSocketMessage(_) => {
+                "Outgoing WebSocket Message"
+            }
             _ => "",
         };
         write!(f, "{info_string}")
@@ -27,8 +32,13 @@ impl<'a> From<&LoggableNT> for Text<'a> {
             LoggableType::OutgoingRequestToServer(i) | LoggableType::IncomingRequestAtFfips(i) => {
                 Text::from(&RequestInfoNT(i.clone()))
             }
-            LoggableType::IncomingResponseFromServer(i)
-            | LoggableType::OutGoingResponseFromFips(i) => Text::from(&ResponseInfoNT(i.clone())),
+            LoggableType::OutGoingResponseFromFips(i) => {
+                Text::from(&ResponseInfoNT(i.clone()))
+            }
+            LoggableType::IncomingWebSocketMessage(i)
+            | LoggableType::OutgoingWebSocketMessage(i) => {
+                Text::from(format!("{}\n{}", i.uri, i.message))
+            }
             _ => Text::from(""),
         }
     }
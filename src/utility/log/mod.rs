@@ -1,14 +1,14 @@
 // Type definitions for logging, implementation is left to the respective crates
 use std::collections::HashMap;
+use std::fmt;
 use hyper::{Response, Request};
 
 #[derive(Debug, Clone)]
 pub struct RequestInfo {
-    pub request_type: String,
     pub method: String,
     pub uri: String,
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HashMap<String, Vec<String>>,
 }
 
 impl<B> From<&Request<B>> for RequestInfo {
@@ -16,15 +16,14 @@ impl<B> From<&Request<B>> for RequestInfo {
         let method = String::from(request.method().as_str());
         let uri = request.uri().to_string();
         let version = format!("{:?}", request.version());
-        let mut headers = HashMap::new();
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
         for (k, v) in request.headers() {
-            headers.insert(
-                String::from(k.as_str()),
-                String::from(v.to_str().unwrap()),
-            );
+            headers
+                .entry(String::from(k.as_str()))
+                .or_default()
+                .push(String::from_utf8_lossy(v.as_bytes()).into_owned());
         }
         RequestInfo{
-            request_type: String::from("placeholder"),
             method,
             uri,
             version,
@@ -33,27 +32,32 @@ impl<B> From<&Request<B>> for RequestInfo {
     }
 }
 
+impl fmt::Display for RequestInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.method, self.uri, self.version)?;
+        write_headers(f, &self.headers)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ResponseInfo {
-    pub response_type: String,
     pub status: String,
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HashMap<String, Vec<String>>,
 }
 
 impl<B> From<&Response<B>> for ResponseInfo {
     fn from(response: &Response<B>) -> ResponseInfo {
         let status = String::from(response.status().as_str());
         let version = format!("{:?}", response.version());
-        let mut headers = HashMap::new();
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
         for (k, v) in response.headers() {
-            headers.insert(
-                String::from(k.as_str()),
-                String::from(v.to_str().unwrap()),
-            );
+            headers
+                .entry(String::from(k.as_str()))
+                .or_default()
+                .push(String::from_utf8_lossy(v.as_bytes()).into_owned());
         }
         ResponseInfo{
-            response_type: String::from("placeholder"),
             status,
             version,
             headers,
@@ -61,12 +65,44 @@ impl<B> From<&Response<B>> for ResponseInfo {
     }
 }
 
+impl fmt::Display for ResponseInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.version)?;
+        write_headers(f, &self.headers)
+    }
+}
+
+fn write_headers(
+    f: &mut fmt::Formatter,
+    headers: &HashMap<String, Vec<String>>,
+) -> fmt::Result {
+    for (k, values) in headers {
+        for v in values {
+            write!(f, "\n{k} : {v}")?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSocketInfo {
+    pub uri: String,
+    pub message: String,
+}
+
+impl fmt::Display for WebSocketInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}", self.uri, self.message)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LoggableType {
   IncomingRequestAtFfips(RequestInfo),
   OutGoingResponseFromFips(ResponseInfo),
   OutgoingRequestToServer(RequestInfo),
-  IncomingResponseFromServer(ResponseInfo),
+  IncomingWebSocketMessage(WebSocketInfo),
+  OutgoingWebSocketMessage(WebSocketInfo),
   Plain
 }
 
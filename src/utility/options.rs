@@ -15,4 +15,16 @@ pub struct CliOptions {
     pub port: u16,
     #[clap(long)]
     pub write_schema: bool,
+    /// Decrypt CONNECT tunnels with certificates of a generated CA instead
+    /// of passing them through
+    #[clap(long)]
+    pub mitm: bool,
+    /// Where the CA certificate (ca.pem) and key (ca.key, only readable by
+    /// its owner) of --mitm are kept
+    #[clap(long, default_value = "fips-ca")]
+    pub ca_dir: PathBuf,
+    /// Seed for probabilities and random load balancing, a random one is
+    /// picked and logged when not given
+    #[clap(long)]
+    pub seed: Option<u64>,
 }